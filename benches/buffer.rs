@@ -0,0 +1,89 @@
+//! Compares `Buffer` against the plain `Vec<String>` storage it replaced,
+//! which inserted and removed lines one `Vec` operation at a time.
+//!
+//! Run with `cargo bench`.
+
+extern crate rsed;
+
+use std::time::{
+    Duration,
+    Instant
+};
+
+use rsed::buffer::Buffer;
+
+const FILE_LINES: usize = 200_000;
+const EDITS: usize = 20;
+const BLOCK_LINES: usize = 1_000;
+const LOOKUPS: usize = 100_000;
+
+fn lines(prefix: &str, count: usize) -> Vec<String> {
+    (0..count).map(|n| format!("{} line {}", prefix, n)).collect()
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn report(name: &str, vec: Duration, buffer: Duration) {
+    println!("{:<28} Vec<String> {:>10.2?}   Buffer {:>10.2?}", name, vec, buffer);
+}
+
+fn main() {
+    let mut vec = lines("file", FILE_LINES);
+    let mut buffer = lines("file", FILE_LINES).into_iter().collect::<Buffer>();
+
+    let paste = lines("paste", BLOCK_LINES);
+
+    let vec_insert = time(|| {
+        for _ in 0..EDITS {
+            let pos = vec.len() / 2;
+            for (index, line) in paste.iter().cloned().enumerate() {
+                vec.insert(pos + index, line);
+            }
+        }
+    });
+    let buffer_insert = time(|| {
+        for _ in 0..EDITS {
+            let pos = buffer.len() / 2;
+            buffer.insert_lines(pos, paste.iter().cloned());
+        }
+    });
+    report("insert 1000 lines x20", vec_insert, buffer_insert);
+
+    let vec_delete = time(|| {
+        for _ in 0..EDITS {
+            let start = vec.len() / 3;
+            for _ in 0..BLOCK_LINES {
+                vec.remove(start);
+            }
+        }
+    });
+    let buffer_delete = time(|| {
+        for _ in 0..EDITS {
+            let start = buffer.len() / 3;
+            buffer.delete_lines(start, start + BLOCK_LINES);
+        }
+    });
+    report("delete 1000 lines x20", vec_delete, buffer_delete);
+
+    let mut vec_total = 0;
+    let vec_lookup = time(|| {
+        for n in 0..LOOKUPS {
+            let index = (n * 7919) % vec.len();
+            vec_total += vec[index].len();
+        }
+    });
+    let mut buffer_total = 0;
+    let buffer_lookup = time(|| {
+        for n in 0..LOOKUPS {
+            let index = (n * 7919) % buffer.len();
+            buffer_total += buffer.get_line(index).map_or(0, str::len);
+        }
+    });
+    report("lookup single line x100000", vec_lookup, buffer_lookup);
+
+    assert_eq!(vec_total, buffer_total);
+}
@@ -1,40 +1,32 @@
 
 use std::iter::FromIterator;
+use std::io;
 use std::io::{
     BufRead,
+    Read,
     Write
 };
 use std::vec::IntoIter;
 use std::ops;
+use std::path::Path;
+use std::rc::Rc;
 
 
 use Result;
 
-#[derive(Debug)]
-pub struct Buffer {
-    lines: Vec<String>,
-    cached_num_lines: usize,
-    modified: bool
-}
-
-trait InsertAll {
-    type Item;
-
-    fn insert_all<I: IntoIterator<Item=Self::Item>>(&mut self, pos: usize, insert: I) -> usize;
-}
-
-impl <T> InsertAll for Vec<T> {
-    type Item = T;
+use mapped::MappedFile;
+use rope::Rope;
 
-    fn insert_all<I: IntoIterator<Item=Self::Item>>(&mut self, pos: usize, insert: I) -> usize {
-        let mut count = 0 as usize;
-        for (index, item) in insert.into_iter().enumerate() {
-            self.insert( pos + index, item );
-            count += 1;
-        }
+pub use rope::Lines;
 
-        count
-    }
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    lines: Rope,
+    modified: bool,
+    /// The file lines are still read from, for a buffer that mapped one.
+    mapping: Option<Rc<MappedFile>>,
+    /// Whether lines end in CR LF, as the first line read did.
+    crlf: bool
 }
 
 impl FromIterator<String> for Buffer {
@@ -42,7 +34,7 @@ impl FromIterator<String> for Buffer {
         where T: IntoIterator<Item=String> {
             let mut buffer = Buffer::new();
             buffer.insert_lines(0, iter);
-            return buffer
+            buffer
         }
 }
 
@@ -51,7 +43,13 @@ impl IntoIterator for Buffer {
     type IntoIter = IntoIter<String>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.lines.into_iter()
+        self.lines.into_vec().into_iter()
+    }
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -59,63 +57,127 @@ impl Buffer {
 
     pub fn new() -> Buffer {
         Buffer { 
-            lines: Vec::new(),
-            cached_num_lines: 0 as usize,
-            modified: false
+            lines: Rope::new(),
+            modified: false,
+            mapping: None,
+            crlf: false
         }
     }
 
-    pub fn from_buf_read<R: BufRead + Sized> (buf_read: R) -> Result<Buffer> {
+    /// Reads lines ending in LF or CR LF. The buffer writes its lines with
+    /// the ending of the first one.
+    pub fn from_buf_read<R: BufRead + Sized> (mut buf_read: R) -> Result<Buffer> {
 
-        let lines = buf_read.lines();
+        let mut first = String::new();
+        buf_read.read_line(&mut first)?;
+        let crlf = first.ends_with("\r\n");
 
-        let lines_vec = lines.map(|r| r.unwrap()).collect::<Vec<String>>();
-        let cached_len = lines_vec.len();
+        let lines = first.as_bytes().chain(buf_read).lines().collect::<io::Result<Vec<String>>>()?;
+
+        let mut rope = Rope::new();
+        rope.insert(0, lines);
 
         Ok(Buffer {
-            lines: lines_vec,
-            cached_num_lines: cached_len,
-            modified: false
+            lines: rope,
+            modified: false,
+            mapping: None,
+            crlf
         })
 
     }
 
+    /// Memory maps the file at `path` instead of reading it. Lines are only
+    /// copied out of the mapping once they are edited.
+    pub fn map_file<P: AsRef<Path>>(path: P) -> Result<Buffer> {
+        let file = Rc::new(MappedFile::open(path)?);
+
+        Ok(Buffer {
+            lines: Rope::from_mapped(file.clone()),
+            modified: false,
+            crlf: file.has_crlf(),
+            mapping: Some(file)
+        })
+    }
+
     pub fn insert_lines<I: IntoIterator<Item=String>>(&mut self, pos: usize, insert: I) {
-        let added_lines = self.lines.insert_all(pos, insert);
-        self.cached_num_lines += added_lines;
-        self.modified = true;
+        self.lines_mut().insert(pos, insert);
     }
 
     pub fn insert_buffer(&mut self, pos: usize, buffer: Buffer) {
-        self.lines.insert_all(pos, buffer.lines);
-        self.cached_num_lines += buffer.cached_num_lines;
-        self.modified = true;
+        self.lines_mut().insert(pos, buffer.lines.into_vec());
     }
 
     pub fn add_line(&mut self, line: String) {
-        self.lines.push(line);
-        self.cached_num_lines += 1;
-        self.modified = true;
+        self.lines_mut().push(line);
+    }
+
+    pub fn replace_line(&mut self, index: usize, line: String) {
+        let lines = self.lines_mut();
+        lines.remove(index, index + 1);
+        lines.insert(index, Some(line));
     }
 
     pub fn delete_lines(&mut self, start: usize, end: usize) {
-        for _ in start..end {
-            self.lines.remove(start);
-        }
+        self.lines_mut().remove(start, end);
+    }
 
-        self.cached_num_lines -= end - start;
+    /// The lines, for a change to them.
+    fn lines_mut(&mut self) -> &mut Rope {
+        self.modified = true;
+        &mut self.lines
     }
 
     pub fn is_empty(&self) -> bool {
-        self.cached_num_lines == 0
+        self.lines.is_empty()
     }
 
     pub fn has_changes(&self) -> bool {
         self.modified
     }
 
+    pub fn set_modified(&mut self) {
+        self.modified = true;
+    }
+
+    pub fn set_unmodified(&mut self) {
+        self.modified = false;
+    }
+
+    /// Whether the buffer was loaded from a memory mapped file.
+    pub fn is_mapped(&self) -> bool {
+        self.mapping.is_some()
+    }
+
+    /// Fails if the lines of a mapped buffer can't be trusted anymore,
+    /// because the file changed on disk or isn't valid UTF-8.
+    pub fn check_mapping(&self) -> Result<()> {
+        match self.mapping {
+            Some(ref file) => file.check(),
+            None => Ok(())
+        }
+    }
+
+    /// Copies the lines of a mapped buffer into memory, so that its file
+    /// can be written to.
+    pub fn unmap(&mut self) {
+        if self.mapping.take().is_some() {
+            let lines = self.lines.lines(0, self.len()).map(str::to_string).collect::<Vec<_>>();
+            self.lines = Rope::new();
+            self.lines.insert(0, lines);
+        }
+    }
+
+    /// Whether lines are written with CR LF instead of LF.
+    pub fn is_crlf(&self) -> bool {
+        self.crlf
+    }
+
+    pub fn set_crlf(&mut self, crlf: bool) {
+        self.crlf = crlf;
+    }
+
     pub fn len(&self) -> usize {
-        self.cached_num_lines
+        self.lines.len()
     }
 
     pub fn is_out_of_bounds(&self, pos: usize) -> bool {
@@ -126,18 +188,25 @@ impl Buffer {
         self.is_out_of_bounds(range.start) || self.is_out_of_bounds(range.end)
     }
 
-    pub fn get_lines(&self, range: &ops::Range<usize>) -> &[String] {
-        assert!(! self.is_out_of_bounds( range.start ), format!("Out of bounds: {}/0", range.start) );
-        assert!(! self.is_out_of_bounds( range.end ), format!("Out of bounds: {}/{}", range.end, self.len()) );
+    /// The lines in `range`, without copying them.
+    pub fn lines(&self, range: &ops::Range<usize>) -> Lines<'_> {
+        assert!(! self.is_out_of_bounds( range.start ), "Out of bounds: {}/0", range.start );
+        assert!(! self.is_out_of_bounds( range.end ), "Out of bounds: {}/{}", range.end, self.len() );
 
 
-        &self.lines[ range.start .. range.end ]
+        self.lines.lines( range.start, range.end )
+    }
+
+    pub fn get_line(&self, index: usize) -> Option<&str> {
+        self.lines.get(index)
     }
 
     pub fn write<W: Write>(&self, w:&mut W) -> Result<()> {
-        
-        for line in self.lines.iter() {
-            try!( w.write_all( line.as_bytes() ) );
+        let ending: &[u8] = if self.crlf { b"\r\n" } else { b"\n" };
+
+        for line in self.lines.lines(0, self.len()) {
+            w.write_all( line.as_bytes() )?;
+            w.write_all( ending )?;
         }
 
         Ok(())
@@ -146,3 +215,39 @@ impl Buffer {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[&str]) -> Buffer {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn lines_follow_changes() {
+        let mut buffer = buffer(&["a", "b", "c"]);
+        assert_eq!(buffer.lines(&(1..3)).collect::<Vec<_>>(), &["b", "c"]);
+
+        buffer.delete_lines(0, 1);
+        assert_eq!(buffer.lines(&(0..2)).collect::<Vec<_>>(), &["b", "c"]);
+
+        buffer.insert_lines(1, vec!["x".to_string()]);
+        buffer.replace_line(2, "z".to_string());
+        buffer.add_line("d".to_string());
+        assert_eq!(buffer.lines(&(0..4)).collect::<Vec<_>>(), &["b", "x", "z", "d"]);
+        assert_eq!(buffer.lines(&(1..3)).collect::<Vec<_>>(), &["x", "z"]);
+        assert!(buffer.has_changes());
+    }
+
+    #[test]
+    fn reads_and_writes_lines() {
+        let buffer = Buffer::from_buf_read(io::Cursor::new("one\ntwo\n\nfour")).unwrap();
+        assert_eq!(buffer.len(), 4);
+        assert!(!buffer.has_changes());
+
+        let mut written = Vec::new();
+        buffer.write(&mut written).unwrap();
+        assert_eq!(written, b"one\ntwo\n\nfour\n");
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::str;
+
+use encoding_rs::{
+    self,
+    Encoding
+};
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// Character encoding of a file, along with whether it starts with a byte
+/// order mark.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Charset {
+    encoding: &'static Encoding,
+    bom: bool
+}
+
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding> {
+    match Encoding::for_label_no_replacement(label.as_bytes()) {
+        Some(encoding) => Ok(encoding),
+        None => Err(Error::detailed(ErrorType::UnknownEncoding, format!("unknown encoding '{}'", label)))
+    }
+}
+
+impl Charset {
+
+    pub fn new(encoding: &'static Encoding, bom: bool) -> Charset {
+        Charset {
+            encoding,
+            bom
+        }
+    }
+
+    pub fn utf8() -> Charset {
+        Charset::new(encoding_rs::UTF_8, false)
+    }
+
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    pub fn is_utf8(&self) -> bool {
+        self.encoding == encoding_rs::UTF_8
+    }
+
+    /// Decodes `bytes` to text. A byte order mark decides the encoding,
+    /// without one `encoding` is used, or UTF-8 if that is `None`.
+    ///
+    /// Malformed input is an error rather than being replaced, so that
+    /// writing the buffer back can't silently corrupt the file.
+    pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> Result<(Charset, String)> {
+        let (charset, text) = match Encoding::for_bom(bytes) {
+            Some((encoding, bom_len)) => (Charset::new(encoding, true), &bytes[bom_len..]),
+            None => (Charset::new(encoding.unwrap_or(encoding_rs::UTF_8), false), bytes)
+        };
+
+        match charset.encoding.decode_without_bom_handling_and_without_replacement(text) {
+            Some(decoded) => Ok((charset, decoded.into_owned())),
+            None => Err(Error::detailed(ErrorType::InvalidEncoding(charset), format!("file is not valid {}", charset.encoding.name())))
+        }
+    }
+
+    fn encode(&self, text: &str) -> Result<Vec<u8>> {
+        if self.encoding == encoding_rs::UTF_16LE {
+            return Ok(text.encode_utf16().flat_map(|u| vec![u as u8, (u >> 8) as u8]).collect());
+        }
+
+        if self.encoding == encoding_rs::UTF_16BE {
+            return Ok(text.encode_utf16().flat_map(|u| vec![(u >> 8) as u8, u as u8]).collect());
+        }
+
+        let (bytes, _, unmappable) = self.encoding.encode(text);
+        if unmappable {
+            return Err(Error::detailed(ErrorType::InvalidEncoding(*self), format!("text can't be represented in {}", self.encoding.name())));
+        }
+
+        Ok(bytes.into_owned())
+    }
+
+    fn bom_bytes(&self) -> &'static [u8] {
+        if !self.bom {
+            b""
+        } else if self.encoding == encoding_rs::UTF_8 {
+            b"\xef\xbb\xbf"
+        } else if self.encoding == encoding_rs::UTF_16LE {
+            b"\xff\xfe"
+        } else if self.encoding == encoding_rs::UTF_16BE {
+            b"\xfe\xff"
+        } else {
+            b""
+        }
+    }
+
+    /// Wraps `writer` so that UTF-8 text written to it is transcoded,
+    /// starting with the byte order mark if there is one.
+    pub fn encoder<W: Write>(self, writer: W) -> CharsetWriter<W> {
+        CharsetWriter {
+            charset: self,
+            writer,
+            pending: Vec::new(),
+            started: false,
+            error: None
+        }
+    }
+
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.encoding.name())?;
+        if self.bom {
+            write!(f, " bom")?;
+        }
+        Ok(())
+    }
+}
+
+/// Transcoding writer created by `Charset::encoder`.
+pub struct CharsetWriter<W: Write> {
+    charset: Charset,
+    writer: W,
+    pending: Vec<u8>,
+    started: bool,
+    /// Why the last write failed, if it was the text that couldn't be
+    /// encoded.
+    error: Option<Error>
+}
+
+impl <W: Write> CharsetWriter<W> {
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Takes the encoding error behind the `io::Error` a write failed with.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+}
+
+impl <W: Write> Write for CharsetWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.started {
+            self.writer.write_all(self.charset.bom_bytes())?;
+            self.started = true;
+        }
+
+        if self.charset.is_utf8() {
+            self.writer.write_all(buf)?;
+            return Ok(buf.len());
+        }
+
+        self.pending.extend_from_slice(buf);
+
+        // keep an incomplete character at the end for the next call
+        let complete = match str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(ref e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+
+        let encoded = {
+            let text = str::from_utf8(&self.pending[..complete]).unwrap();
+            match self.charset.encode(text) {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    let io_error = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                    self.error = Some(e);
+                    return Err(io_error);
+                }
+            }
+        };
+
+        self.writer.write_all(&encoded)?;
+        self.pending.drain(..complete);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(charset: Charset, text: &str) -> io::Result<Vec<u8>> {
+        let mut writer = charset.encoder(Vec::new());
+        // split inside the multibyte é to cover characters spanning writes
+        let (head, tail) = text.as_bytes().split_at(text.len() / 2);
+        writer.write_all(head)?;
+        writer.write_all(tail)?;
+        Ok(writer.into_inner())
+    }
+
+    #[test]
+    fn decodes_by_bom_first() {
+        let (charset, text) = Charset::decode(b"\xff\xfeh\0i\0", Some(encoding_rs::WINDOWS_1252)).unwrap();
+        assert_eq!(charset, Charset::new(encoding_rs::UTF_16LE, true));
+        assert_eq!(text, "hi");
+
+        let (charset, text) = Charset::decode(b"caf\xe9", Some(encoding_rs::WINDOWS_1252)).unwrap();
+        assert_eq!(charset, Charset::new(encoding_rs::WINDOWS_1252, false));
+        assert_eq!(text, "café");
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        match Charset::decode(b"caf\xe9", None).map_err(|e| e.error) {
+            Err(ErrorType::InvalidEncoding(charset)) => assert!(charset.is_utf8()),
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for &(encoding, bom) in &[(encoding_rs::UTF_8, true), (encoding_rs::UTF_16LE, true),
+                                  (encoding_rs::UTF_16BE, true), (encoding_rs::WINDOWS_1252, false)] {
+            let charset = Charset::new(encoding, bom);
+            let bytes = encode(charset, "café\nau lait\n").unwrap();
+            assert_eq!(Charset::decode(&bytes, Some(encoding)).unwrap(), (charset, "café\nau lait\n".to_string()));
+        }
+    }
+
+    #[test]
+    fn unmappable_text_keeps_its_error() {
+        let mut writer = Charset::new(encoding_rs::WINDOWS_1252, false).encoder(Vec::new());
+        assert!(writer.write_all("漢字".as_bytes()).is_err());
+
+        match writer.take_error().map(|e| e.error) {
+            Some(ErrorType::InvalidEncoding(_)) => (),
+            other => panic!("unexpected error {:?}", other)
+        }
+    }
+}
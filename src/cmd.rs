@@ -1,11 +1,10 @@
-
 use std::str;
-
-use regex::Regex;
+use std::fmt;
 
 use pos;
+use search::Direction;
+use ui;
 use ui::PrintOption;
-use util::FlipResultOption;
 
 use {
     Result,
@@ -13,115 +12,1221 @@ use {
     ErrorType
 };
 
-#[derive(Debug)]
+/// Names of the built-in `:name` commands, which aliases can't shadow.
+const EXTENDED_COMMANDS: &[&str] = &[
+    "encoding", "visual", "history", "redo", "record", "macros", "set", "alias",
+    "reload", "diff"
+];
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Cmd {
     EnterInsertMode(pos::Range),
     Quit,
     Debug(pos::Range),
     Jump(pos::Range),
-    Delete(pos::Range),
+    Delete(pos::Range, Option<char>),
     JumpNext,
     Print(pos::Range, PrintOption),
     PrintLineNumber(pos::Range),
     Edit(String),
-    Write(Option<String>)
+    Write(Option<String>, bool),
+    Mark(pos::Range, char),
+    Transfer(pos::Range, pos::Target),
+    Move(pos::Range, pos::Target),
+    OpenBuffer(String),
+    ListBuffers,
+    SwitchBuffer(usize),
+    CloseBuffer(Option<usize>),
+    Yank(pos::Range, Option<char>),
+    Put(pos::Range, Option<char>),
+    Encoding(Option<String>, Option<bool>),
+    ListMatches(pos::Range, String, usize),
+    Scroll(Option<pos::Range>, Option<usize>, PrintOption),
+    Visual,
+    ListHistory,
+    Rerun(usize),
+    RepeatChange(Option<pos::Range>),
+    Record(Option<char>),
+    RunMacro(Option<pos::Range>, char, usize),
+    ListMacros,
+    SaveMacros(String),
+    LoadMacros(String),
+    Set(Option<String>, Option<String>),
+    ListAliases,
+    DefineAlias(String, String),
+    RunAlias(String),
+    Reload(bool),
+    Diff(Option<DiffTarget>, Option<usize>),
+    Substitute(pos::Range, Substitution),
+    Global(pos::Range, String, bool, Vec<String>)
+}
+
+/// The pattern, replacement and flags of `s/re/replacement/flags`.
+///
+/// The replacement is kept as typed except for escaped delimiters and
+/// newlines: `&` stands for the match, `\1` to `\9` for its groups, a
+/// newline splits the line and a lone `%` is the previous replacement.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Substitution {
+    pub pattern: String,
+    pub replacement: String,
+    /// Replace every match from the `nth` one on, not only the `nth`.
+    pub global: bool,
+    pub nth: usize
+}
+
+/// What `:diff` compares the current buffer with, instead of its file.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DiffTarget {
+    File(String),
+    Buffer(usize)
 }
 
-impl str::FromStr for Cmd {
+/// A parsed command line: the command itself plus an optional print suffix
+/// (`p`, `n` or `l`) that prints the current line once the command is done.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Command {
+    pub cmd: Cmd,
+    pub suffix: Option<PrintOption>
+}
+
+impl Command {
+    pub fn new(cmd: Cmd) -> Command {
+        Command {
+            cmd,
+            suffix: None
+        }
+    }
+
+    pub fn with_suffix(cmd: Cmd, suffix: PrintOption) -> Command {
+        Command {
+            cmd,
+            suffix: Some(suffix)
+        }
+    }
+}
+
+impl str::FromStr for Command {
     type Err = Error;
 
-    fn from_str(s: &str) -> Result<Cmd> {
-        try!(s.parse::<ParsedData>()).to_cmd()
+    fn from_str(s: &str) -> Result<Command> {
+        Parser::new(s).parse()
     }
 }
 
-static COMMAND_RE: &'static str = r"^((?P<range>[%.,$\d]+)?(?P<cmd>[a-zA-Z?=])?( (?P<arg>.*))?)$";
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cmd {
+            Cmd::EnterInsertMode(ref r) => write!(f, "{}i", r)?,
+            Cmd::Quit => write!(f, "q")?,
+            Cmd::Debug(ref r) => write!(f, "{}?", r)?,
+            Cmd::Jump(ref r) => write!(f, "{}", r)?,
+            Cmd::Delete(ref r, register) => write!(f, "{}d{}", r, RegisterName(register))?,
+            Cmd::JumpNext => (),
+            Cmd::Print(ref r, ref option) => write!(f, "{}{}", r, print_char(option))?,
+            Cmd::PrintLineNumber(ref r) => write!(f, "{}=", r)?,
+            Cmd::Edit(ref file) => write!(f, "e {}", file)?,
+            Cmd::Write(ref file, force) => {
+                write!(f, "w{}", if force { "!" } else { "" })?;
+                if let Some(ref file) = *file {
+                    write!(f, " {}", file)?;
+                }
+            },
+            Cmd::Mark(ref r, c) => write!(f, "{}k{}", r, c)?,
+            Cmd::Transfer(ref r, ref target) => write!(f, "{}t{}", r, target)?,
+            Cmd::Move(ref r, ref target) => write!(f, "{}m{}", r, target)?,
+            Cmd::OpenBuffer(ref file) => write!(f, "B {}", file)?,
+            Cmd::ListBuffers => write!(f, "b")?,
+            Cmd::SwitchBuffer(n) => write!(f, "b {}", n)?,
+            Cmd::CloseBuffer(Some(n)) => write!(f, "bd {}", n)?,
+            Cmd::CloseBuffer(None) => write!(f, "bd")?,
+            Cmd::Yank(ref r, register) => write!(f, "{}y{}", r, RegisterName(register))?,
+            Cmd::Put(ref r, register) => write!(f, "{}x{}", r, RegisterName(register))?,
+            Cmd::Encoding(None, _) => write!(f, ":encoding")?,
+            Cmd::Encoding(Some(ref label), None) => write!(f, ":encoding {}", label)?,
+            Cmd::Encoding(Some(ref label), Some(bom)) => write!(f, ":encoding {} {}", label, if bom { "bom" } else { "nobom" })?,
+            Cmd::ListMatches(ref r, ref pattern, context) => {
+                write!(f, "{}L", r)?;
+                pos::write_pattern(f, pattern, '/')?;
+                if context > 0 {
+                    write!(f, "{}", context)?;
+                }
+            },
+            Cmd::Visual => write!(f, ":visual")?,
+            Cmd::ListHistory => write!(f, ":history")?,
+            Cmd::Rerun(n) => write!(f, ":redo {}", n)?,
+            Cmd::RepeatChange(Some(ref r)) => write!(f, "{}&", r)?,
+            Cmd::RepeatChange(None) => write!(f, "&")?,
+            Cmd::Record(Some(c)) => write!(f, ":record {}", c)?,
+            Cmd::Record(None) => write!(f, ":record")?,
+            Cmd::RunMacro(ref r, c, count) => {
+                if let Some(ref r) = *r {
+                    write!(f, "{}", r)?;
+                }
+                write!(f, "@{}", c)?;
+                if count != 1 {
+                    write!(f, "{}", count)?;
+                }
+            },
+            Cmd::ListMacros => write!(f, ":macros")?,
+            Cmd::SaveMacros(ref file) => write!(f, ":macros save {}", file)?,
+            Cmd::LoadMacros(ref file) => write!(f, ":macros load {}", file)?,
+            Cmd::Set(None, _) => write!(f, ":set")?,
+            Cmd::Set(Some(ref name), None) => write!(f, ":set {}", name)?,
+            Cmd::Set(Some(ref name), Some(ref value)) => {
+                if value.is_empty() || value.trim() != value || value.starts_with('"') {
+                    write!(f, ":set {}=\"{}\"", name, value)?;
+                } else {
+                    write!(f, ":set {}={}", name, value)?;
+                }
+            },
+            Cmd::ListAliases => write!(f, ":alias")?,
+            Cmd::DefineAlias(ref name, ref body) => write!(f, ":alias {} {}", name, body)?,
+            Cmd::RunAlias(ref name) => write!(f, ":{}", name)?,
+            Cmd::Reload(force) => write!(f, ":reload{}", if force { "!" } else { "" })?,
+            Cmd::Diff(ref target, context) => {
+                write!(f, ":diff")?;
+                if let Some(n) = context {
+                    write!(f, " -U {}", n)?;
+                }
+                match *target {
+                    Some(DiffTarget::File(ref file)) => write!(f, " {}", file)?,
+                    Some(DiffTarget::Buffer(n)) => write!(f, " #{}", n)?,
+                    None => ()
+                }
+            },
+            Cmd::Substitute(ref r, ref substitution) => {
+                write!(f, "{}s", r)?;
+                pos::write_pattern(f, &substitution.pattern, '/')?;
+                write_replacement(f, &substitution.replacement, '/')?;
+                if substitution.global {
+                    write!(f, "g")?;
+                }
+                if substitution.nth != 1 {
+                    write!(f, "{}", substitution.nth)?;
+                }
+            },
+            Cmd::Global(ref r, ref pattern, invert, ref commands) => {
+                write!(f, "{}{}", r, if invert { 'v' } else { 'g' })?;
+                pos::write_pattern(f, pattern, '/')?;
+                write!(f, "{}", commands.join("\\\n"))?;
+            },
+            Cmd::Scroll(ref r, lines, ref option) => {
+                if let Some(ref r) = *r {
+                    write!(f, "{}", r)?;
+                }
+                write!(f, "z")?;
+                if let Some(n) = lines {
+                    write!(f, "{}", n)?;
+                }
+                if *option != PrintOption::Normal {
+                    write!(f, "{}", print_char(option))?;
+                }
+            }
+        }
 
-struct ParsedData {
-    cmd_char: Option<char>,
-    range: Option<pos::Range>,
-    arg: Option<String>
+        match self.suffix {
+            Some(ref option) => write!(f, "{}", print_char(option)),
+            None => Ok(())
+        }
+    }
 }
 
-impl ParsedData {
-    fn is_empty(&self) -> bool {
-        match (&self.cmd_char, &self.range, &self.arg) {
-            (&None, &None, &None) => true,
-            _ => false
+/// Writes the replacement of `s` followed by `delimiter`, escaping the
+/// delimiter and the newlines in it.
+fn write_replacement(f: &mut fmt::Formatter, replacement: &str, delimiter: char) -> fmt::Result {
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            write!(f, "{}", c)?;
+            if let Some(next) = chars.next() {
+                write!(f, "{}", next)?;
+            }
+        } else if c == delimiter || c == '\n' {
+            write!(f, "\\{}", c)?;
+        } else {
+            write!(f, "{}", c)?;
         }
     }
 
-    fn empty() -> ParsedData {
-        ParsedData {
-            cmd_char: None,
-            range: None,
-            arg: None
+    write!(f, "{}", delimiter)
+}
+
+struct RegisterName(Option<char>);
+
+impl fmt::Display for RegisterName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(c) => write!(f, "\"{}", c),
+            None => Ok(())
         }
     }
+}
+
+fn print_char(option: &PrintOption) -> char {
+    match *option {
+        PrintOption::Normal => 'p',
+        PrintOption::Numbered => 'n',
+        PrintOption::LineEndings => 'l'
+    }
+}
 
-    fn to_cmd(self) -> Result<Cmd> {
-        if self.is_empty() {
-            return Ok(Cmd::JumpNext);
+fn print_option(c: char) -> Option<PrintOption> {
+    match c {
+        'p' => Some(PrintOption::Normal),
+        'n' => Some(PrintOption::Numbered),
+        'l' => Some(PrintOption::LineEndings),
+        _ => None
+    }
+}
+
+/// Hand-written recursive descent parser for a single command line.
+///
+/// Errors carry the byte offset of the offending character as
+/// `ErrorType::SyntaxError`.
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize
+}
+
+impl <'a> Parser<'a> {
+
+    fn new(src: &'a str) -> Parser<'a> {
+        Parser {
+            src,
+            pos: 0
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c {
+            self.pos += c.len_utf8();
+        }
+        c
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
         }
+    }
+
+    fn rest(&mut self) -> &'a str {
+        let rest = &self.src[self.pos..];
+        self.pos = self.src.len();
+        rest
+    }
+
+    fn error_at<T: ToString>(&self, pos: usize, msg: T) -> Error {
+        Error::detailed(ErrorType::SyntaxError(pos), msg)
+    }
+
+    fn error<T: ToString>(&self, msg: T) -> Error {
+        self.error_at(self.pos, msg)
+    }
+
+    fn parse(mut self) -> Result<Command> {
+        let range_start = self.pos;
+        let range = self.parse_range()?;
 
-        let range = self.range.unwrap_or_else( pos::Range::current_line );
-        let arg = self.arg.ok_or( Error::detailed(ErrorType::ParseError, "arg expected") );
+        let cmd_start = self.pos;
+        let cmd_char = match self.bump() {
+            Some(c) => c,
+            None => return Ok(Command::new(match range {
+                Some(r) => Cmd::Jump(r),
+                None => Cmd::JumpNext
+            }))
+        };
+
+        let no_range = |parser: &Parser, cmd: Cmd| -> Result<Cmd> {
+            match range {
+                Some(_) => Err(parser.error_at(range_start, "unexpected address")),
+                None => Ok(cmd)
+            }
+        };
+        let addressed = range.is_some();
+        let range = range.clone().unwrap_or_else( pos::Range::current_line );
+        let mut implied_suffix = None;
 
-        if let Some(c) = self.cmd_char {
-            match c {
-                'd' => expect_no_arg(arg, Cmd::Delete(range)),
-                'i' => expect_no_arg(arg, Cmd::EnterInsertMode(range)),
-                'q' => expect_no_arg(arg, Cmd::Quit),
-                'p' => expect_no_arg(arg, Cmd::Print(range, PrintOption::Normal)),
-                'n' => expect_no_arg(arg, Cmd::Print(range, PrintOption::Numbered)),
-                'l' => expect_no_arg(arg, Cmd::Print(range, PrintOption::LineEndings)),
-                '=' => expect_no_arg(arg, Cmd::PrintLineNumber(range)),
-                '?' => expect_no_arg(arg, Cmd::Debug(range)),
-                'w' => Ok(Cmd::Write(arg.ok())),
-                'e' => Ok(Cmd::Edit(try!(arg))),
-                _ => Err(Error::detailed(ErrorType::ParseError, "unknown command"))
+        let cmd = match cmd_char {
+            'd' => Cmd::Delete(range, self.parse_register()?),
+            'y' => Cmd::Yank(range, self.parse_register()?),
+            'x' => Cmd::Put(range, self.parse_register()?),
+            'i' => Cmd::EnterInsertMode(range),
+            'q' => no_range(&self, Cmd::Quit)?,
+            '=' => Cmd::PrintLineNumber(range),
+            '?' => Cmd::Debug(range),
+            'e' => {
+                let file = self.parse_file_name()?;
+                match file {
+                    Some(f) => no_range(&self, Cmd::Edit(f))?,
+                    None => return Err(self.error("file name expected"))
+                }
+            },
+            'w' => {
+                // w! writes even if the file changed on disk
+                let force = self.eat('!');
+                let file = self.parse_file_name()?;
+                no_range(&self, Cmd::Write(file, force))?
+            },
+            'k' => Cmd::Mark(range, self.parse_mark_name()?),
+            't' => Cmd::Transfer(range, self.parse_target()?),
+            'm' => Cmd::Move(range, self.parse_target()?),
+            'B' => {
+                let file = self.parse_file_name()?;
+                match file {
+                    Some(f) => no_range(&self, Cmd::OpenBuffer(f))?,
+                    None => return Err(self.error("file name expected"))
+                }
+            },
+            'z' => {
+                let lines = self.parse_number()?;
+                let option = self.parse_suffix().unwrap_or(PrintOption::Normal);
+                // without an address, scrolling starts after the current line
+                Cmd::Scroll(if addressed { Some(range) } else { None }, lines, option)
+            },
+            'L' => {
+                let pattern = self.parse_delimited_pattern()?;
+                let context = self.parse_number()?.unwrap_or(0);
+                // without an address the whole buffer is searched
+                let range = if addressed {
+                    range
+                } else {
+                    pos::Range::Range(pos::Pos::Line(1), pos::Pos::End)
+                };
+                Cmd::ListMatches(range, pattern, context)
+            },
+            '@' => {
+                let name = self.parse_macro_name()?;
+                let count_start = self.pos;
+                let count = self.parse_number()?;
+                match (addressed, count) {
+                    (true, Some(_)) => return Err(self.error_at(count_start, "unexpected count")),
+                    (true, None) => Cmd::RunMacro(Some(range), name, 1),
+                    (false, count) => Cmd::RunMacro(None, name, count.unwrap_or(1))
+                }
+            },
+            's' => {
+                let (substitution, closed) = self.parse_substitution()?;
+                if !closed {
+                    implied_suffix = Some(PrintOption::Normal);
+                }
+                Cmd::Substitute(range, substitution)
+            },
+            'g' | 'v' => {
+                let delimiter = self.parse_delimiter()?;
+                let (pattern, closed) = self.parse_closed_pattern(delimiter)?;
+                if !closed {
+                    return Err(self.error("missing delimiter"));
+                }
+                let commands = self.parse_command_list()?;
+                // without an address the whole buffer is searched
+                let range = if addressed {
+                    range
+                } else {
+                    pos::Range::Range(pos::Pos::Line(1), pos::Pos::End)
+                };
+                Cmd::Global(range, pattern, cmd_char == 'v', commands)
+            },
+            // without an address, the change is repeated at the current line
+            '&' => Cmd::RepeatChange(if addressed { Some(range) } else { None }),
+            ':' => {
+                let cmd = self.parse_extended()?;
+                no_range(&self, cmd)?
+            },
+            'b' => {
+                let close = self.eat('d');
+                let n = self.parse_buffer_number()?;
+                no_range(&self, match (close, n) {
+                    (true, n) => Cmd::CloseBuffer(n),
+                    (false, Some(n)) => Cmd::SwitchBuffer(n),
+                    (false, None) => Cmd::ListBuffers
+                })?
+            },
+            c => match print_option(c) {
+                Some(option) => {
+                    // a print suffix on a print command just overrides its mode
+                    let option = self.parse_suffix().unwrap_or(option);
+                    Cmd::Print(range, option)
+                },
+                None => return Err(self.error_at(cmd_start, format!("unknown command '{}'", c)))
             }
+        };
+
+        let suffix = if takes_suffix(&cmd) {
+            self.parse_suffix().or(implied_suffix)
         } else {
-            expect_no_arg(arg, Cmd::Jump(range))
+            None
+        };
+
+        if self.pos < self.src.len() {
+            return Err(self.error("unexpected character"));
         }
+
+        Ok(Command {
+            cmd,
+            suffix
+        })
     }
-}
 
-fn expect_no_arg(arg: Result<String>, cmd: Cmd) -> Result<Cmd> {
-    match arg {
-        Err(_) => Ok(cmd),
-        _ => Err(Error::detailed(ErrorType::ParseError, "no arg expected"))
+    /// Parses the name and arguments of a `:name` command.
+    fn parse_extended(&mut self) -> Result<Cmd> {
+        let name_start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_lowercase()) {
+            self.bump();
+        }
+        let name = &self.src[name_start..self.pos];
+        let bang_start = self.pos;
+        let bang = self.eat('!');
+
+        // errors in the arguments point past the space before them
+        let args_start = if self.peek() == Some(' ') { self.pos + 1 } else { self.pos };
+        let args = self.parse_args()?;
+
+        if bang && name != "reload" {
+            return Err(self.error_at(bang_start, "unexpected '!'"));
+        }
+
+        match name {
+            "encoding" => match args.len() {
+                0 => Ok(Cmd::Encoding(None, None)),
+                1 => Ok(Cmd::Encoding(Some(args[0].to_string()), None)),
+                2 => match args[1] {
+                    "bom" => Ok(Cmd::Encoding(Some(args[0].to_string()), Some(true))),
+                    "nobom" => Ok(Cmd::Encoding(Some(args[0].to_string()), Some(false))),
+                    _ => Err(self.error_at(args_start, "expected 'bom' or 'nobom'"))
+                },
+                _ => Err(self.error_at(args_start, "too many arguments"))
+            },
+            "visual" if args.is_empty() => Ok(Cmd::Visual),
+            "visual" => Err(self.error_at(args_start, "too many arguments")),
+            "history" if args.is_empty() => Ok(Cmd::ListHistory),
+            "history" => Err(self.error_at(args_start, "too many arguments")),
+            "redo" => match args.len() {
+                1 => match args[0].parse() {
+                    Ok(n) => Ok(Cmd::Rerun(n)),
+                    Err(_) => Err(self.error_at(args_start, "invalid number"))
+                },
+                0 => Err(self.error_at(args_start, "history number expected")),
+                _ => Err(self.error_at(args_start, "too many arguments"))
+            },
+            "record" => match args.len() {
+                0 => Ok(Cmd::Record(None)),
+                1 => {
+                    let mut chars = args[0].chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if c.is_ascii_lowercase() => Ok(Cmd::Record(Some(c))),
+                        _ => Err(self.error_at(args_start, "macro name expected"))
+                    }
+                },
+                _ => Err(self.error_at(args_start, "too many arguments"))
+            },
+            "macros" => match (args.first().cloned(), args.len()) {
+                (None, _) => Ok(Cmd::ListMacros),
+                (Some("save"), 2) => Ok(Cmd::SaveMacros(args[1].to_string())),
+                (Some("load"), 2) => Ok(Cmd::LoadMacros(args[1].to_string())),
+                (Some("save"), _) | (Some("load"), _) => Err(self.error_at(args_start, "file name expected")),
+                _ => Err(self.error_at(args_start, "expected 'save' or 'load'"))
+            },
+            "reload" if args.is_empty() => Ok(Cmd::Reload(bang)),
+            "reload" => Err(self.error_at(args_start, "too many arguments")),
+            "set" => self.parse_set(args_start),
+            "diff" => self.parse_diff(&args, args_start),
+            "alias" => match args.first() {
+                None => Ok(Cmd::ListAliases),
+                Some(_) if args.len() == 1 => Err(self.error_at(args_start, "alias body expected")),
+                Some(alias) if !alias.chars().all(|c| c.is_ascii_lowercase()) => {
+                    Err(self.error_at(args_start, "alias names are made of lowercase letters"))
+                },
+                Some(alias) if EXTENDED_COMMANDS.contains(alias) => {
+                    Err(self.error_at(args_start, format!("':{}' is a command", alias)))
+                },
+                Some(alias) => {
+                    // the body is kept as typed, up to the trailing whitespace
+                    let body = self.src[args_start..].trim();
+                    Ok(Cmd::DefineAlias(alias.to_string(), body[alias.len()..].trim_start().to_string()))
+                }
+            },
+            "" => Err(self.error_at(name_start, "command name expected")),
+            // other names may be aliases, which are only known when run
+            _ if args.is_empty() => Ok(Cmd::RunAlias(name.to_string())),
+            _ => Err(self.error_at(name_start, format!("unknown command ':{}'", name)))
+        }
     }
-}
-    
-impl str::FromStr for ParsedData {
-    type Err = Error;
 
-    fn from_str(s: &str) -> Result<ParsedData> {
+    /// Parses the arguments of `:set`, either nothing, an option name, or
+    /// `name=value` where the value may be quoted to keep surrounding
+    /// spaces.
+    fn parse_set(&self, args_start: usize) -> Result<Cmd> {
+        let args = self.src[args_start..].trim();
+        let args_start = self.src.len() - self.src[args_start..].trim_start().len();
+
+        if args.is_empty() {
+            return Ok(Cmd::Set(None, None));
+        }
+
+        let (name, value) = match args.find('=') {
+            Some(n) => (&args[..n], Some(&args[n + 1..])),
+            None => (args, None)
+        };
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase()) {
+            return Err(self.error_at(args_start, "option name expected"));
+        }
+
+        let value = value.map(|value| {
+            if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                value[1..value.len() - 1].to_string()
+            } else {
+                value.to_string()
+            }
+        });
+
+        Ok(Cmd::Set(Some(name.to_string()), value))
+    }
+
+    /// Parses the arguments of `:diff`, an optional `-U N` for the number of
+    /// context lines followed by an optional file name or `#N` for buffer N.
+    fn parse_diff(&self, args: &[&str], args_start: usize) -> Result<Cmd> {
+        let (context, rest) = match args.first() {
+            Some(&"-U") => match args.get(1).map(|n| n.parse()) {
+                Some(Ok(n)) => (Some(n), &args[2..]),
+                _ => return Err(self.error_at(args_start, "number of context lines expected"))
+            },
+            _ => (None, args)
+        };
+
+        let target = match rest.first() {
+            None => None,
+            Some(_) if rest.len() > 1 => return Err(self.error_at(args_start, "too many arguments")),
+            Some(arg) if arg.starts_with('#') => match arg[1..].parse() {
+                Ok(n) => Some(DiffTarget::Buffer(n)),
+                Err(_) => return Err(self.error_at(args_start, "invalid buffer number"))
+            },
+            Some(file) => Some(DiffTarget::File(file.to_string()))
+        };
+
+        Ok(Cmd::Diff(target, context))
+    }
+
+    /// Splits the rest of the line into space separated arguments.
+    fn parse_args(&mut self) -> Result<Vec<&'a str>> {
+        if self.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        if !self.eat(' ') {
+            return Err(self.error("expected ' ' before arguments"));
+        }
+
+        Ok(self.rest().split_whitespace().collect())
+    }
 
-        if s.len() == 0 {
-            return Ok(ParsedData::empty());
+    fn parse_suffix(&mut self) -> Option<PrintOption> {
+        match self.peek().and_then(print_option) {
+            Some(option) => {
+                self.bump();
+                Some(option)
+            },
+            None => None
         }
+    }
 
-        let re = try!(Regex::new(COMMAND_RE));
+    fn parse_file_name(&mut self) -> Result<Option<String>> {
+        if self.peek().is_none() {
+            return Ok(None);
+        }
 
-        if let Some(captures) = re.captures(s) {
+        if !self.eat(' ') {
+            return Err(self.error("expected ' ' before file name"));
+        }
 
-            let cmd_range = try!(captures.name("range").map(|r| r.parse()).flip());
+        let file = self.rest();
+        if file.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(file.to_string()))
+        }
+    }
 
-            let cmd_char = captures.name("cmd").and_then(|c| c.chars().next());
+    fn parse_mark_name(&mut self) -> Result<char> {
+        match self.peek() {
+            Some(c) if c.is_ascii_lowercase() => {
+                self.bump();
+                Ok(c)
+            },
+            _ => Err(self.error("mark name expected"))
+        }
+    }
 
-            let cmd_arg = captures.name("arg").map(str::to_string);
-            
-            Ok(ParsedData {
-                cmd_char: cmd_char,
-                range: cmd_range,
-                arg: cmd_arg
-            })
+    fn parse_macro_name(&mut self) -> Result<char> {
+        match self.peek() {
+            Some(c) if c.is_ascii_lowercase() => {
+                self.bump();
+                Ok(c)
+            },
+            _ => Err(self.error("macro name expected"))
+        }
+    }
 
+    /// Parses an optional `"x` register name.
+    fn parse_register(&mut self) -> Result<Option<char>> {
+        if self.eat('"') {
+            match self.peek() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.bump();
+                    Ok(Some(c))
+                },
+                _ => Err(self.error("register name expected"))
+            }
         } else {
-            Err(Error::new(ErrorType::ParseError))
+            Ok(None)
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Option<usize>> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+
+        if start == self.pos {
+            return Ok(None);
+        }
+
+        match self.src[start..self.pos].parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(self.error_at(start, "invalid number"))
+        }
+    }
+
+    fn parse_buffer_number(&mut self) -> Result<Option<usize>> {
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+
+        if !self.eat(' ') {
+            return Err(self.error("expected ' ' before buffer number"));
+        }
+
+        match self.parse_number()? {
+            Some(n) => Ok(Some(n)),
+            None => Err(self.error("buffer number expected"))
+        }
+    }
+
+    /// Parses the destination of `t` and `m`: an address, optionally
+    /// prefixed with `<buffer>:` to point into another buffer.
+    fn parse_target(&mut self) -> Result<pos::Target> {
+        let start = self.pos;
+
+        if let Some(n) = self.parse_number()? {
+            if self.eat(':') {
+                return match self.parse_pos()? {
+                    Some(p) => Ok(pos::Target::in_buffer(n, p)),
+                    None => Err(self.error("address expected"))
+                };
+            }
+        }
+
+        self.pos = start;
+
+        match self.parse_pos()? {
+            Some(p) => Ok(pos::Target::new(p)),
+            None => Ok(pos::Target::new(pos::Pos::Current))
         }
     }
+
+    /// Reads a pattern up to an unescaped `delimiter` or the end of the
+    /// line. An escaped delimiter is unescaped, other escapes are kept for
+    /// the regex. A backslash at the end of the line escapes nothing and
+    /// is an error, as in ed.
+    fn parse_pattern(&mut self, delimiter: char) -> Result<String> {
+        self.parse_closed_pattern(delimiter).map(|(pattern, _)| pattern)
+    }
+
+    /// Reads a pattern like `parse_pattern`, also returning whether it
+    /// ended with the delimiter rather than the line.
+    fn parse_closed_pattern(&mut self, delimiter: char) -> Result<(String, bool)> {
+        let mut pattern = String::new();
+
+        while let Some(c) = self.bump() {
+            if c == delimiter {
+                return Ok((pattern, true));
+            }
+
+            if c == '\\' {
+                match self.bump() {
+                    Some(next) if next == delimiter => pattern.push(next),
+                    Some(next) => {
+                        pattern.push(c);
+                        pattern.push(next);
+                    },
+                    None => return Err(self.error_at(self.pos - 1, "trailing backslash"))
+                }
+            } else {
+                pattern.push(c);
+            }
+        }
+
+        Ok((pattern, false))
+    }
+
+    /// Parses a pattern enclosed in a delimiter of the user's choice, as in
+    /// `/re/` or `|re|`.
+    fn parse_delimited_pattern(&mut self) -> Result<String> {
+        let delimiter = self.parse_delimiter()?;
+        self.parse_pattern(delimiter)
+    }
+
+    fn parse_delimiter(&mut self) -> Result<char> {
+        match self.peek() {
+            Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => {
+                self.bump();
+                Ok(c)
+            },
+            _ => Err(self.error("pattern delimiter expected"))
+        }
+    }
+
+    /// Parses `re/replacement/flags` after `s`. Without the last delimiter
+    /// the flags are left out and the line printed, as in ed.
+    fn parse_substitution(&mut self) -> Result<(Substitution, bool)> {
+        let delimiter = self.parse_delimiter()?;
+        let (pattern, closed) = self.parse_closed_pattern(delimiter)?;
+        if !closed {
+            return Err(self.error("missing delimiter"));
+        }
+
+        let (replacement, closed) = self.parse_replacement(delimiter)?;
+
+        // `g` and a count, in either order
+        let mut global = false;
+        let mut nth = None;
+        loop {
+            if !global && self.eat('g') {
+                global = true;
+                continue;
+            }
+
+            let start = self.pos;
+            match self.parse_number()? {
+                Some(0) => return Err(self.error_at(start, "invalid number")),
+                Some(n) if nth.is_none() => nth = Some(n),
+                Some(_) => return Err(self.error_at(start, "unexpected count")),
+                None => break
+            }
+        }
+
+        let substitution = Substitution {
+            pattern,
+            replacement,
+            global,
+            nth: nth.unwrap_or(1)
+        };
+        Ok((substitution, closed))
+    }
+
+    /// Reads the replacement of `s` up to an unescaped `delimiter` or the
+    /// end of the input, returning whether the delimiter was found. An
+    /// escaped delimiter or newline is unescaped, other escapes are kept. A
+    /// backslash ending the input continues the replacement on the next
+    /// line, which makes the command `Unfinished`.
+    fn parse_replacement(&mut self, delimiter: char) -> Result<(String, bool)> {
+        let mut replacement = String::new();
+
+        while let Some(c) = self.bump() {
+            if c == delimiter {
+                return Ok((replacement, true));
+            }
+
+            if c == '\\' {
+                match self.bump() {
+                    Some(next) if next == delimiter || next == '\n' => replacement.push(next),
+                    Some(next) => {
+                        replacement.push(c);
+                        replacement.push(next);
+                    },
+                    None => return Err(unfinished())
+                }
+            } else {
+                replacement.push(c);
+            }
+        }
+
+        Ok((replacement, false))
+    }
+
+    /// Splits the rest of the input into the command list of `g` or `v`,
+    /// one command per line. Every line but the last ends with a backslash,
+    /// a backslash ending the last one makes the command `Unfinished`.
+    ///
+    /// The commands are checked here, so that a list that can't run is
+    /// rejected before any line is marked. They are parsed like typed
+    /// lines, text for `i` included, and may not be `g` or `v` again.
+    fn parse_command_list(&mut self) -> Result<Vec<String>> {
+        let start = self.pos;
+        let rest = self.rest();
+        if rest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut commands = Vec::new();
+        let mut parser = ui::Ui::new();
+        let mut offset = start;
+        let mut lines = rest.split('\n').peekable();
+
+        while let Some(line) = lines.next() {
+            let continued = line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1;
+            match (continued, lines.peek().is_some()) {
+                (true, false) => return Err(unfinished()),
+                (false, true) => return Err(self.error_at(offset + line.len(), "expected '\\' before newline")),
+                _ => ()
+            }
+
+            let command = if continued { &line[..line.len() - 1] } else { line };
+            let action = parser.parse_input(command).map_err(|e| match *e.error_type() {
+                ErrorType::SyntaxError(pos) => self.error_at(offset + pos, e.message()),
+                _ => e
+            })?;
+
+            if let Some(ref action) = action {
+                if let ui::Action::Command(Command { cmd: Cmd::Global(..), .. }) = *action {
+                    return Err(self.error_at(offset, "nested global command"));
+                }
+                parser.follow(action);
+            }
+
+            commands.push(command.to_string());
+            offset += line.len() + 1;
+        }
+
+        Ok(commands)
+    }
+
+    fn parse_range(&mut self) -> Result<Option<pos::Range>> {
+        if self.eat('%') {
+            return Ok(Some(pos::Range::Range(pos::Pos::Line(1), pos::Pos::End)));
+        }
+
+        let first = match self.parse_pos()? {
+            Some(p) => p,
+            None => return Ok(None)
+        };
+
+        if !self.eat(',') {
+            return Ok(Some(pos::Range::Line(first)));
+        }
+
+        match self.parse_pos()? {
+            Some(second) => Ok(Some(pos::Range::Range(first, second))),
+            None => Err(self.error("address expected"))
+        }
+    }
+
+    fn parse_pos(&mut self) -> Result<Option<pos::Pos>> {
+        match self.peek() {
+            Some('.') => {
+                self.bump();
+                Ok(Some(pos::Pos::Current))
+            },
+            Some('$') => {
+                self.bump();
+                Ok(Some(pos::Pos::End))
+            },
+            Some('\'') => {
+                self.bump();
+                Ok(Some(pos::Pos::Mark(self.parse_mark_name()?)))
+            },
+            Some('/') => {
+                self.bump();
+                let pattern = self.parse_pattern('/')?;
+                Ok(Some(pos::Pos::Search(pattern, Direction::Forward)))
+            },
+            // a lone '?' is the debug command, not a search
+            Some('?') if self.src.len() > self.pos + 1 => {
+                self.bump();
+                let pattern = self.parse_pattern('?')?;
+                Ok(Some(pos::Pos::Search(pattern, Direction::Backward)))
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let n = self.parse_number()?;
+                Ok(n.map(pos::Pos::Line))
+            },
+            _ => Ok(None)
+        }
+    }
+}
+
+fn takes_suffix(cmd: &Cmd) -> bool {
+    matches!(*cmd, Cmd::Delete(_, _) | Cmd::Transfer(_, _) | Cmd::Move(_, _) |
+                   Cmd::Yank(_, _) | Cmd::Put(_, _) | Cmd::RepeatChange(_) |
+                   Cmd::Substitute(_, _))
+}
+
+/// The error for input that continues on the next line.
+fn unfinished() -> Error {
+    Error::detailed(ErrorType::Unfinished, "command continues on the next line")
+}
+
+/// Byte offset at which the file name starts in `line`, if it holds a
+/// command taking one: `e`, `w`, `B` or `:diff`. The rest of the line isn't
+/// checked, the file name may be unfinished.
+pub fn file_name_start(line: &str) -> Option<usize> {
+    let mut parser = Parser::new(line);
+    parser.parse_range().ok()?;
+
+    match parser.bump()? {
+        'e' | 'B' => (),
+        'w' => {
+            parser.eat('!');
+        },
+        ':' => {
+            let name_start = parser.pos;
+            while parser.peek().is_some_and(|c| c.is_ascii_lowercase()) {
+                parser.bump();
+            }
+
+            // the file name comes last, after the options
+            return match &line[name_start..parser.pos] {
+                "diff" if parser.eat(' ') => line.rfind(' ').map(|n| n + 1),
+                _ => None
+            };
+        },
+        _ => return None
+    }
+
+    if parser.eat(' ') {
+        Some(parser.pos)
+    } else {
+        None
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pos::{
+        Pos,
+        Range,
+        Target
+    };
+    use search::Direction;
+    use ErrorType;
+
+    fn parse(s: &str) -> Command {
+        match s.parse() {
+            Ok(command) => command,
+            Err(e) => panic!("'{}' doesn't parse: {}", s, e)
+        }
+    }
+
+    fn assert_round_trip(command: &Command) {
+        let text = command.to_string();
+        assert_eq!(&parse(&text), command, "'{}' doesn't parse back", text);
+    }
+
+    fn error_position(s: &str) -> usize {
+        match s.parse::<Command>() {
+            Err(e) => match *e.error_type() {
+                ErrorType::SyntaxError(pos) => pos,
+                ref other => panic!("'{}': unexpected error {:?}", s, other)
+            },
+            Ok(command) => panic!("'{}' parses as {:?}", s, command)
+        }
+    }
+
+    /// Index of the variant of `cmd`, the match makes sure a new variant
+    /// can't be added without a round trip test.
+    fn variant(cmd: &Cmd) -> usize {
+        match *cmd {
+            Cmd::EnterInsertMode(_) => 0,
+            Cmd::Quit => 1,
+            Cmd::Debug(_) => 2,
+            Cmd::Jump(_) => 3,
+            Cmd::Delete(_, _) => 4,
+            Cmd::JumpNext => 5,
+            Cmd::Print(_, _) => 6,
+            Cmd::PrintLineNumber(_) => 7,
+            Cmd::Edit(_) => 8,
+            Cmd::Write(_, _) => 9,
+            Cmd::Mark(_, _) => 10,
+            Cmd::Transfer(_, _) => 11,
+            Cmd::Move(_, _) => 12,
+            Cmd::OpenBuffer(_) => 13,
+            Cmd::ListBuffers => 14,
+            Cmd::SwitchBuffer(_) => 15,
+            Cmd::CloseBuffer(_) => 16,
+            Cmd::Yank(_, _) => 17,
+            Cmd::Put(_, _) => 18,
+            Cmd::Encoding(_, _) => 19,
+            Cmd::ListMatches(_, _, _) => 20,
+            Cmd::Scroll(_, _, _) => 21,
+            Cmd::Visual => 22,
+            Cmd::ListHistory => 23,
+            Cmd::Rerun(_) => 24,
+            Cmd::RepeatChange(_) => 25,
+            Cmd::Record(_) => 26,
+            Cmd::RunMacro(_, _, _) => 27,
+            Cmd::ListMacros => 28,
+            Cmd::SaveMacros(_) => 29,
+            Cmd::LoadMacros(_) => 30,
+            Cmd::Set(_, _) => 31,
+            Cmd::ListAliases => 32,
+            Cmd::DefineAlias(_, _) => 33,
+            Cmd::RunAlias(_) => 34,
+            Cmd::Reload(_) => 35,
+            Cmd::Diff(_, _) => 36,
+            Cmd::Substitute(_, _) => 37,
+            Cmd::Global(_, _, _, _) => 38
+        }
+    }
+
+    const VARIANTS: usize = 39;
+
+    const INPUTS: &[&str] = &[
+        "i", "2i", "$i", "q", "?", "1,2?", "5", "'a", "", "d", "1,$d\"a", "d\"a",
+        "p", "3n", "1,2l", "pn", "=", "$=", "e file.txt", "w", "w!", "w out.txt",
+        "w! out.txt", "ka", "2,3kz", "t0", "1,2t$", "t2:5", "m'a", "2m1:/x/",
+        "B other.txt", "b", "b 2", "bd", "bd 3", "y", "1,2y\"b", "y\"B", "x", "$x\"c",
+        ":encoding", ":encoding latin1", ":encoding utf-16le bom", ":encoding utf-8 nobom",
+        "L/foo/", "1,5L|a/b|3", "L/a\\/b/", "z", "z10", "5z3n", "zl", ":visual", ":history",
+        ":redo 3", "&", "2,3&", "&p", ":record a", ":record", "@a", "@b3", "1,4@c",
+        ":macros", ":macros save m.txt", ":macros load m.txt", ":set", ":set prompt",
+        ":set prompt=*", ":set prompt=\"> \"", ":set prompt=\"\"", ":alias",
+        ":alias wq w;q", ":wq", ":reload", ":reload!", ":diff", ":diff -U 5",
+        ":diff other.txt", ":diff -U 0 #2", "/foo/", "?bar?", "/a\\/b/p", "?a\\?b?",
+        "/a\\.b/,/c/d", "1,$p", "dp", "tn", ".,+", "/[/]/", "s/a/b/", "1,$s|x|&&|g",
+        "s/a\\(b\\)/\\1\\/c/3p", "s/a/b\\\nc/2g", "s/a/%/", "g/x/", "2,5v/^$/d",
+        "g|a/b|s/a/c/\\\n1p", "g/x/i\\\ntext\\\n."
+    ];
+
+    #[test]
+    fn parsed_commands_round_trip() {
+        let mut covered = [false; VARIANTS];
+
+        for input in INPUTS {
+            // inputs that aren't valid are skipped, they are tested below
+            let command = match input.parse::<Command>() {
+                Ok(command) => command,
+                Err(_) => continue
+            };
+
+            covered[variant(&command.cmd)] = true;
+            assert_round_trip(&command);
+        }
+
+        let missing: Vec<usize> = (0..VARIANTS).filter(|&n| !covered[n]).collect();
+        assert!(missing.is_empty(), "variants without a round trip test: {:?}", missing);
+    }
+
+    #[test]
+    fn built_commands_round_trip() {
+        let search = |pattern: &str, direction| Pos::Search(pattern.to_string(), direction);
+        let commands = vec![
+            Command::new(Cmd::Print(Range::Range(search("a/b", Direction::Forward), search("c?d", Direction::Backward)),
+                                    PrintOption::Numbered)),
+            Command::new(Cmd::ListMatches(Range::Line(Pos::Current), "x\\.y|z/".to_string(), 2)),
+            Command::new(Cmd::Transfer(Range::Line(Pos::Mark('a')), Target::in_buffer(2, Pos::End))),
+            Command::with_suffix(Cmd::Delete(Range::Line(Pos::Line(3)), Some('r')), PrintOption::LineEndings),
+            Command::new(Cmd::Set(Some("prompt".to_string()), Some(" padded ".to_string()))),
+            Command::new(Cmd::Set(Some("prompt".to_string()), Some("\"quoted\"".to_string()))),
+            Command::new(Cmd::Diff(Some(DiffTarget::Buffer(3)), Some(1))),
+            Command::new(Cmd::RunMacro(None, 'q', 7))
+        ];
+
+        for command in &commands {
+            assert_round_trip(command);
+        }
+    }
+
+    #[test]
+    fn escaped_delimiters() {
+        assert_eq!(parse("/a\\/b/").cmd, Cmd::Jump(Range::Line(Pos::Search("a/b".to_string(), Direction::Forward))));
+        assert_eq!(parse("/a\\.b/").cmd, Cmd::Jump(Range::Line(Pos::Search("a\\.b".to_string(), Direction::Forward))));
+        assert_eq!(parse("/a\\\\/").cmd, Cmd::Jump(Range::Line(Pos::Search("a\\\\".to_string(), Direction::Forward))));
+        assert_eq!(parse("/a\\\\/").to_string(), "/a\\\\/");
+    }
+
+    #[test]
+    fn trailing_backslash_is_an_error() {
+        assert_eq!(error_position("/a\\"), 2);
+        assert_eq!(error_position("L/a\\"), 3);
+        assert_eq!(error_position("?a\\\\\\"), 4);
+    }
+
+    #[test]
+    fn substitution_flags() {
+        let substitution = |replacement: &str, global, nth| Substitution {
+            pattern: "a".to_string(),
+            replacement: replacement.to_string(),
+            global,
+            nth
+        };
+
+        assert_eq!(parse("s/a/b/g3"), Command::new(Cmd::Substitute(Range::current_line(), substitution("b", true, 3))));
+        assert_eq!(parse("s|a|/\\||"), Command::new(Cmd::Substitute(Range::current_line(), substitution("/|", false, 1))));
+        // without the last delimiter the line is printed
+        assert_eq!(parse("s/a/b"), Command::with_suffix(Cmd::Substitute(Range::current_line(), substitution("b", false, 1)),
+                                                        PrintOption::Normal));
+        assert_eq!(error_position("s/a"), 3);
+        assert_eq!(error_position("s/a/b/gg"), 7);
+        assert_eq!(error_position("s/a/b/0"), 6);
+        assert_eq!(error_position("s/a/b/2g3"), 8);
+    }
+
+    #[test]
+    fn continued_command_lists() {
+        let unfinished = |s: &str| match s.parse::<Command>() {
+            Err(ref e) => matches!(*e.error_type(), ErrorType::Unfinished),
+            Ok(_) => false
+        };
+
+        assert!(unfinished("s/a/b\\"));
+        assert!(unfinished("g/x/s/a/b/\\"));
+        assert!(unfinished("g/x/s/a/b\\\ncd\\"));
+        assert!(!unfinished("s/a/b\\\\"));
+
+        assert_eq!(parse("s/a/b\\\nc/").cmd, Cmd::Substitute(Range::current_line(), Substitution {
+            pattern: "a".to_string(),
+            replacement: "b\nc".to_string(),
+            global: false,
+            nth: 1
+        }));
+        assert_eq!(parse("g/x/d\\\n1p").cmd, Cmd::Global(Range::Range(Pos::Line(1), Pos::End), "x".to_string(), false,
+                                                          vec!["d".to_string(), "1p".to_string()]));
+
+        // errors point into the list
+        assert_eq!(error_position("g/x/p\\\n2Q"), 8);
+        assert_eq!(error_position("g/x/p\n1p"), 5);
+        assert_eq!(error_position("v/x/g/y/p"), 4);
+    }
+
+    #[test]
+    fn bang_only_after_reload() {
+        assert_eq!(parse(":reload!").cmd, Cmd::Reload(true));
+        assert_eq!(error_position(":visual!"), 7);
+        assert_eq!(error_position(":history!"), 8);
+        assert_eq!(error_position(":record! a"), 7);
+        assert_eq!(error_position(":set! prompt"), 4);
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error_position("1,2q"), 0);
+        assert_eq!(error_position("2Q"), 1);
+        assert_eq!(error_position("pq"), 1);
+        assert_eq!(error_position("e"), 1);
+        assert_eq!(error_position(":redo x"), 6);
+        assert_eq!(error_position(":diff -U"), 6);
+        assert_eq!(error_position("1@a2"), 3);
+    }
+}
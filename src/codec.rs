@@ -0,0 +1,227 @@
+use std::fmt;
+use std::io;
+use std::io::{
+    Read,
+    Write
+};
+use std::path::Path;
+use std::str;
+
+#[cfg(feature = "gzip")]
+use flate2;
+#[cfg(feature = "zstd")]
+use zstd;
+#[cfg(feature = "xz")]
+use xz2;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// Compression format of a file.
+///
+/// Everything but `Plain` needs the cargo feature of the same name.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Codec {
+    Plain,
+    Gzip,
+    Zstd,
+    Xz
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// Number of bytes needed to recognize any of the supported formats.
+pub const MAGIC_LEN: usize = 6;
+
+impl Codec {
+
+    /// Recognizes a compressed file by its first bytes, falling back to the
+    /// file extension for files without a known magic number, like new or
+    /// empty ones.
+    pub fn detect<P: AsRef<Path>>(path: P, head: &[u8]) -> Codec {
+        if head.starts_with(GZIP_MAGIC) {
+            Codec::Gzip
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Codec::Zstd
+        } else if head.starts_with(XZ_MAGIC) {
+            Codec::Xz
+        } else {
+            Codec::from_extension(path)
+        }
+    }
+
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Codec {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Codec::Gzip,
+            Some("zst") => Codec::Zstd,
+            Some("xz") => Codec::Xz,
+            _ => Codec::Plain
+        }
+    }
+
+    fn unsupported(self) -> Error {
+        Error::detailed(ErrorType::UnsupportedCodec(self), format!("rsed was built without {} support", self))
+    }
+
+    /// Wraps `reader` so that it yields the decompressed content.
+    pub fn decoder<'a, R: Read + 'a>(self, reader: R) -> Result<Box<dyn Read + 'a>> {
+        match self {
+            Codec::Plain => Ok(Box::new(reader)),
+
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+            #[cfg(feature = "xz")]
+            Codec::Xz => Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(reader))),
+
+            #[allow(unreachable_patterns)]
+            other => Err(other.unsupported())
+        }
+    }
+
+    /// Wraps `writer` so that everything written to it gets compressed.
+    pub fn encoder<W: Write>(self, writer: W) -> Result<Encoder<W>> {
+        match self {
+            Codec::Plain => Ok(Encoder::Plain(writer)),
+
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Ok(Encoder::Gzip(flate2::write::GzEncoder::new(writer, flate2::Compression::default()))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(writer, 0)?)),
+            #[cfg(feature = "xz")]
+            Codec::Xz => Ok(Encoder::Xz(xz2::write::XzEncoder::new(writer, 6))),
+
+            #[allow(unreachable_patterns)]
+            other => Err(other.unsupported())
+        }
+    }
+
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Codec::Plain => write!(f, "none"),
+            Codec::Gzip => write!(f, "gzip"),
+            Codec::Zstd => write!(f, "zstd"),
+            Codec::Xz => write!(f, "xz")
+        }
+    }
+}
+
+impl str::FromStr for Codec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Codec> {
+        match s {
+            "none" | "plain" => Ok(Codec::Plain),
+            "gzip" | "gz" => Ok(Codec::Gzip),
+            "zstd" | "zst" => Ok(Codec::Zstd),
+            "xz" => Ok(Codec::Xz),
+            _ => Err(Error::detailed(ErrorType::ParseError, format!("unknown codec '{}'", s)))
+        }
+    }
+}
+
+/// Compressing writer, `finish` must be called to write the trailer.
+pub enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::write::XzEncoder<W>)
+}
+
+impl <W: Write> Encoder<W> {
+    pub fn finish(self) -> Result<W> {
+        match self {
+            Encoder::Plain(w) => Ok(w),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(e) => Ok(e.finish()?),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(e) => Ok(e.finish()?),
+            #[cfg(feature = "xz")]
+            Encoder::Xz(e) => Ok(e.finish()?)
+        }
+    }
+
+    fn inner(&mut self) -> &mut dyn Write {
+        match *self {
+            Encoder::Plain(ref mut w) => w,
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(ref mut e) => e,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(ref mut e) => e,
+            #[cfg(feature = "xz")]
+            Encoder::Xz(ref mut e) => e
+        }
+    }
+}
+
+impl <W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(codec: Codec) {
+        let text = "line\n".repeat(1000);
+
+        let mut encoder = codec.encoder(Vec::new()).unwrap();
+        encoder.write_all(text.as_bytes()).unwrap();
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(Codec::detect("file", &bytes[..MAGIC_LEN]), codec);
+
+        let mut decoded = String::new();
+        codec.decoder(&bytes[..]).unwrap().read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn detects_by_magic_then_extension() {
+        assert_eq!(Codec::detect("file.txt", GZIP_MAGIC), Codec::Gzip);
+        assert_eq!(Codec::detect("file.gz", XZ_MAGIC), Codec::Xz);
+        assert_eq!(Codec::detect("file.zst", b""), Codec::Zstd);
+        assert_eq!(Codec::detect("file.xz", b"plain text"), Codec::Xz);
+        assert_eq!(Codec::detect("file.txt", b"plain text"), Codec::Plain);
+    }
+
+    #[test]
+    fn plain_passes_through() {
+        round_trip(Codec::Plain);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trips() {
+        round_trip(Codec::Gzip);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trips() {
+        round_trip(Codec::Zstd);
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz_round_trips() {
+        round_trip(Codec::Xz);
+    }
+}
@@ -0,0 +1,125 @@
+use std::env;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::str;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// Names of the options known to `:set`, in the order they are listed.
+pub const OPTIONS: &[&str] = &["prompt", "verbose", "color", "dialect", "backup", "diffcontext"];
+
+/// Name of the configuration file, both in the home directory and in the
+/// directory rsed is started in.
+const CONFIG_FILE: &str = ".rsedrc";
+
+/// When the original of a file is copied to `file~` before it is written.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Backup {
+    /// Never.
+    Off,
+    /// Before the first write of the session.
+    Once,
+    /// Before every write.
+    Always
+}
+
+impl fmt::Display for Backup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Backup::Off => write!(f, "off"),
+            Backup::Once => write!(f, "once"),
+            Backup::Always => write!(f, "always")
+        }
+    }
+}
+
+impl str::FromStr for Backup {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Backup> {
+        match s {
+            "off" => Ok(Backup::Off),
+            "once" => Ok(Backup::Once),
+            "always" => Ok(Backup::Always),
+            _ => Err(Error::detailed(ErrorType::ParseError, format!("unknown backup policy '{}'", s)))
+        }
+    }
+}
+
+/// Parses the value of a boolean option.
+pub fn parse_bool(s: &str) -> Result<bool> {
+    match s {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(Error::detailed(ErrorType::ParseError, format!("expected 'on' or 'off', not '{}'", s)))
+    }
+}
+
+pub fn format_bool(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+/// `~/.rsedrc`
+pub fn user_config_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(CONFIG_FILE))
+}
+
+/// `.rsedrc` in the current directory, unless that is the home directory
+/// and it was read as the user's configuration already.
+pub fn project_config_file() -> Option<PathBuf> {
+    let dir = match env::current_dir() {
+        Ok(dir) => dir,
+        Err(_) => return None
+    };
+
+    if env::var_os("HOME").is_some_and(|home| dir == Path::new(&home)) {
+        None
+    } else {
+        Some(dir.join(CONFIG_FILE))
+    }
+}
+
+/// Splits the body of an alias into the input lines it stands for, which
+/// are separated by `;`. A `\;` is a literal semicolon. Whitespace around
+/// each line is dropped.
+pub fn split_alias(body: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut chars = body.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&';') => {
+                chars.next();
+                line.push(';');
+            },
+            ';' => {
+                lines.push(line.trim().to_string());
+                line.clear();
+            },
+            c => line.push(c)
+        }
+    }
+
+    lines.push(line.trim().to_string());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_alias_bodies() {
+        assert_eq!(split_alias(" 1p ; 2d;$p"), &["1p", "2d", "$p"]);
+        assert_eq!(split_alias("s/a/\\;/;w"), &["s/a/;/", "w"]);
+        assert_eq!(split_alias("a;"), &["a", ""]);
+    }
+}
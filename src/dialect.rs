@@ -0,0 +1,407 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str;
+use std::str::Chars;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// POSIX regular expression dialect of user patterns.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Dialect {
+    /// Basic regular expressions, the default of ed.
+    Basic,
+    /// Extended regular expressions, as selected by GNU ed's `-E`.
+    Extended
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Dialect::Basic => write!(f, "basic"),
+            Dialect::Extended => write!(f, "extended")
+        }
+    }
+}
+
+impl str::FromStr for Dialect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Dialect> {
+        match s {
+            "basic" | "bre" => Ok(Dialect::Basic),
+            "extended" | "ere" => Ok(Dialect::Extended),
+            _ => Err(Error::detailed(ErrorType::ParseError, format!("unknown regex dialect '{}'", s)))
+        }
+    }
+}
+
+/// Characters the `regex` crate wants escaped to match literally.
+fn is_meta(c: char) -> bool {
+    matches!(c, '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '|' |
+                '[' | ']' | '{' | '}' | '^' | '$' | '#')
+}
+
+fn push_literal(out: &mut String, c: char) {
+    if is_meta(c) {
+        out.push('\\');
+    }
+    out.push(c);
+}
+
+fn error<T: ToString>(msg: T) -> Error {
+    Error::detailed(ErrorType::ParseError, msg)
+}
+
+/// Translates a POSIX `pattern` into the syntax of the `regex` crate.
+///
+/// Besides the POSIX constructs, the GNU extensions `\+`, `\?` and `\|` in
+/// basic expressions as well as `\w`, `\W`, `\s`, `\S`, `\b`, `\B`, `` \` ``
+/// and `\'` are understood. Both `\<` and `\>` become `\b`, as the `regex`
+/// crate can't tell the start of a word from its end. Back-references are
+/// an error.
+pub fn translate(pattern: &str, dialect: Dialect) -> Result<String> {
+    let translator = Translator {
+        chars: pattern.chars().peekable(),
+        out: String::with_capacity(pattern.len()),
+        dialect,
+        at_start: true
+    };
+
+    translator.translate()
+}
+
+struct Translator<'a> {
+    chars: Peekable<Chars<'a>>,
+    out: String,
+    dialect: Dialect,
+    // at the start of the pattern or of a group or alternative, where `*`
+    // is literal and `^` is an anchor
+    at_start: bool
+}
+
+impl <'a> Translator<'a> {
+
+    fn translate(mut self) -> Result<String> {
+        while let Some(c) = self.chars.next() {
+            let at_start = self.at_start;
+            self.at_start = false;
+
+            match c {
+                '\\' => self.escape(at_start)?,
+                '[' => self.bracket()?,
+                '.' => self.out.push('.'),
+                '*' if at_start => self.out.push_str("\\*"),
+                '*' => self.out.push('*'),
+                '^' if at_start || self.dialect == Dialect::Extended => {
+                    self.out.push('^');
+                    self.at_start = true;
+                },
+                '$' if self.at_end() || self.dialect == Dialect::Extended => self.out.push('$'),
+                _ if self.dialect == Dialect::Extended => self.extended(c, at_start)?,
+                _ => push_literal(&mut self.out, c)
+            }
+        }
+
+        Ok(self.out)
+    }
+
+    /// Whether a `$` just read ends the pattern, a group or an alternative
+    /// of a basic expression.
+    fn at_end(&self) -> bool {
+        let mut rest = self.chars.clone();
+        match rest.next() {
+            None => true,
+            Some('\\') => matches!(rest.next(), Some(')') | Some('|')),
+            _ => false
+        }
+    }
+
+    fn extended(&mut self, c: char, at_start: bool) -> Result<()> {
+        match c {
+            '(' => {
+                self.out.push('(');
+                self.at_start = true;
+            },
+            '|' => {
+                self.out.push('|');
+                self.at_start = true;
+            },
+            ')' => self.out.push(')'),
+            '+' | '?' if at_start => push_literal(&mut self.out, c),
+            '+' | '?' => self.out.push(c),
+            '{' if !at_start && self.interval_follows() => self.interval('}')?,
+            _ => push_literal(&mut self.out, c)
+        }
+
+        Ok(())
+    }
+
+    /// Whether an ERE `{` just read starts an interval rather than being a
+    /// literal brace.
+    fn interval_follows(&self) -> bool {
+        let mut rest = self.chars.clone();
+        match rest.next() {
+            Some(c) if c.is_ascii_digit() => (),
+            _ => return false
+        }
+
+        for c in rest {
+            match c {
+                '}' => return true,
+                ',' => (),
+                c if c.is_ascii_digit() => (),
+                _ => return false
+            }
+        }
+
+        false
+    }
+
+    /// Copies the bounds of an interval whose opening brace was just read,
+    /// up to the closing brace (`}` for ERE or the `}` of `\}` for BRE).
+    fn interval(&mut self, close: char) -> Result<()> {
+        self.out.push('{');
+
+        loop {
+            match self.chars.next() {
+                Some(c) if c.is_ascii_digit() || c == ',' => self.out.push(c),
+                Some('\\') if self.dialect == Dialect::Basic => {
+                    if self.chars.next() != Some(close) {
+                        return Err(error("invalid interval"));
+                    }
+                    break;
+                },
+                Some(c) if c == close && self.dialect == Dialect::Extended => break,
+                Some(_) => return Err(error("invalid interval")),
+                None => return Err(error("unterminated interval"))
+            }
+        }
+
+        self.out.push('}');
+        Ok(())
+    }
+
+    fn escape(&mut self, at_start: bool) -> Result<()> {
+        let c = match self.chars.next() {
+            Some(c) => c,
+            None => return Err(error("trailing backslash"))
+        };
+
+        match c {
+            '1'..='9' => return Err(error(format!("back-reference '\\{}' is not supported", c))),
+            '<' | '>' => self.out.push_str("\\b"),
+            '`' => self.out.push_str("\\A"),
+            '\'' => self.out.push_str("\\z"),
+            'w' | 'W' | 's' | 'S' | 'b' | 'B' | 'n' | 't' => {
+                self.out.push('\\');
+                self.out.push(c);
+            },
+            _ if self.dialect == Dialect::Extended => push_literal(&mut self.out, c),
+            '(' => {
+                self.out.push('(');
+                self.at_start = true;
+            },
+            '|' => {
+                self.out.push('|');
+                self.at_start = true;
+            },
+            ')' => self.out.push(')'),
+            '+' | '?' if !at_start => self.out.push(c),
+            '{' if !at_start => self.interval('}')?,
+            _ => push_literal(&mut self.out, c)
+        }
+
+        Ok(())
+    }
+
+    /// Translates a bracket expression whose `[` was just read. Backslashes
+    /// are literal inside of it, and a leading `]` is a member.
+    fn bracket(&mut self) -> Result<()> {
+        self.out.push('[');
+
+        if self.chars.peek() == Some(&'^') {
+            self.chars.next();
+            self.out.push('^');
+        }
+
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            self.out.push_str("\\]");
+        }
+
+        loop {
+            match self.chars.next() {
+                Some(']') => break,
+                Some('[') => match self.chars.peek().cloned() {
+                    Some(':') => {
+                        self.chars.next();
+                        let name = self.bracket_term(':')?;
+                        self.out.push_str("[:");
+                        self.out.push_str(&name);
+                        self.out.push_str(":]");
+                    },
+                    Some(kind @ '=') | Some(kind @ '.') => {
+                        self.chars.next();
+                        let term = self.bracket_term(kind)?;
+                        let mut chars = term.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) => push_literal(&mut self.out, c),
+                            _ => return Err(error(format!("'[{}{}{}]' is not supported", kind, term, kind)))
+                        }
+                    },
+                    _ => self.out.push_str("\\[")
+                },
+                Some('-') => self.out.push('-'),
+                Some(c) => push_literal(&mut self.out, c),
+                None => return Err(error("unterminated bracket expression"))
+            }
+        }
+
+        self.out.push(']');
+        Ok(())
+    }
+
+    /// Reads the contents of a `[:class:]`, `[=c=]` or `[.c.]` term up to
+    /// its closing `kind` and `]`.
+    fn bracket_term(&mut self, kind: char) -> Result<String> {
+        let mut term = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some(c) if c == kind && self.chars.peek() == Some(&']') => {
+                    self.chars.next();
+                    return Ok(term);
+                },
+                Some(c) => term.push(c),
+                None => return Err(error("unterminated bracket expression"))
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn basic(pattern: &str) -> String {
+        translate(pattern, Dialect::Basic).unwrap()
+    }
+
+    fn extended(pattern: &str) -> String {
+        translate(pattern, Dialect::Extended).unwrap()
+    }
+
+    fn is_error(pattern: &str, dialect: Dialect) -> bool {
+        match translate(pattern, dialect) {
+            Err(e) => matches!(*e.error_type(), ErrorType::ParseError),
+            Ok(_) => false
+        }
+    }
+
+    fn is_match(pattern: &str, dialect: Dialect, text: &str) -> bool {
+        Regex::new(&translate(pattern, dialect).unwrap()).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn basic_groups_and_intervals() {
+        assert_eq!(basic("\\(ab\\)*"), "(ab)*");
+        assert_eq!(basic("a\\{2,3\\}"), "a{2,3}");
+        assert_eq!(basic("a\\{2\\}b\\{1,\\}"), "a{2}b{1,}");
+        assert_eq!(basic("a\\|b\\+c\\?"), "a|b+c?");
+        assert!(is_error("a\\{2,3}", Dialect::Basic));
+        assert!(is_error("a\\{2", Dialect::Basic));
+
+        // a `*` starting the pattern or a group is literal
+        assert_eq!(basic("*a"), "\\*a");
+        assert_eq!(basic("\\(*a\\)"), "(\\*a)");
+    }
+
+    #[test]
+    fn basic_literals() {
+        assert_eq!(basic("a+b?c|d"), "a\\+b\\?c\\|d");
+        assert_eq!(basic("a{1}"), "a\\{1\\}");
+        assert_eq!(basic("(a)"), "\\(a\\)");
+        assert_eq!(basic("\\.\\*\\[\\\\"), "\\.\\*\\[\\\\");
+        assert!(is_match("f(x)+1", Dialect::Basic, "f(x)+1"));
+        assert!(!is_match("f(x)+1", Dialect::Basic, "fxx1"));
+    }
+
+    #[test]
+    fn extended_groups_and_intervals() {
+        assert_eq!(extended("(ab)*"), "(ab)*");
+        assert_eq!(extended("a{2,3}"), "a{2,3}");
+        assert_eq!(extended("a+b?c|d"), "a+b?c|d");
+        assert!(is_match("^(ab|cd){2}$", Dialect::Extended, "abcd"));
+        assert!(!is_match("^(ab|cd){2}$", Dialect::Extended, "ab"));
+    }
+
+    #[test]
+    fn extended_literals() {
+        assert_eq!(extended("\\(a\\)\\{1\\}"), "\\(a\\)\\{1\\}");
+        assert_eq!(extended("\\+\\?\\|"), "\\+\\?\\|");
+        // braces that don't make an interval and repeats with nothing to
+        // repeat are literal
+        assert_eq!(extended("a{x}"), "a\\{x\\}");
+        assert_eq!(extended("{1}"), "\\{1\\}");
+        assert_eq!(extended("+a"), "\\+a");
+        assert_eq!(extended("(?a)"), "(\\?a)");
+    }
+
+    #[test]
+    fn bracket_expressions() {
+        assert_eq!(basic("[]a]"), "[\\]a]");
+        assert_eq!(basic("[^]a]"), "[^\\]a]");
+        assert_eq!(basic("[a^]"), "[a\\^]");
+        assert_eq!(basic("[[:digit:]x-z]"), "[[:digit:]x-z]");
+        assert_eq!(basic("[^[:space:]]"), "[^[:space:]]");
+        assert_eq!(basic("[[=a=][.-.]]"), "[a-]");
+        assert_eq!(extended("[a\\]"), "[a\\\\]");
+        assert_eq!(extended("[(|)]"), "[\\(\\|\\)]");
+
+        assert!(is_match("^[]x]*$", Dialect::Basic, "]x]"));
+        assert!(is_match("^[^]x]$", Dialect::Basic, "a"));
+        assert!(!is_match("^[^]x]$", Dialect::Basic, "]"));
+        assert!(is_match("^[[:digit:]]*$", Dialect::Extended, "042"));
+
+        assert!(is_error("[abc", Dialect::Basic));
+        assert!(is_error("[[:alpha]", Dialect::Basic));
+        assert!(is_error("[[=ab=]]", Dialect::Extended));
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(basic("^a$"), "^a$");
+        // in the middle of a basic expression they are literal, except at
+        // the ends of groups and alternatives
+        assert_eq!(basic("a^b$c"), "a\\^b\\$c");
+        assert_eq!(basic("\\(^a$\\)"), "(^a$)");
+        assert_eq!(basic("a$\\|^b"), "a$|^b");
+        assert!(is_match("x^y", Dialect::Basic, "x^y"));
+
+        // extended expressions always anchor
+        assert_eq!(extended("a^b$c"), "a^b$c");
+        assert_eq!(extended("(^a|b$)"), "(^a|b$)");
+        assert!(!is_match("x^y", Dialect::Extended, "x^y"));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_eq!(basic("\\<word\\>"), "\\bword\\b");
+        assert_eq!(extended("\\<word\\>"), "\\bword\\b");
+        assert!(is_match("\\<on\\>", Dialect::Basic, "go on now"));
+        assert!(!is_match("\\<on\\>", Dialect::Basic, "gone"));
+    }
+
+    #[test]
+    fn back_references_are_errors() {
+        assert!(is_error("\\(a\\)\\1", Dialect::Basic));
+        assert!(is_error("(a)\\1", Dialect::Extended));
+        assert!(is_error("a\\", Dialect::Basic));
+    }
+}
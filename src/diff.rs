@@ -0,0 +1,297 @@
+use std::cmp;
+use std::fmt;
+
+use signal;
+
+use Result;
+
+/// Context lines around the changes of a unified diff, unless set otherwise.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// One step of the edit script turning the old lines into the new ones.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edit {
+    /// Old line `.0` is kept as new line `.1`.
+    Keep(usize, usize),
+    /// Old line `.0` is removed.
+    Delete(usize),
+    /// New line `.0` is added.
+    Insert(usize)
+}
+
+/// Computes a shortest edit script from `old` to `new` with the linear space
+/// variant of Myers' algorithm.
+///
+/// The middle snake of an optimal path splits the problem in two, each
+/// solved the same way, so only O(N + M) state is kept besides the script.
+/// It can be interrupted.
+pub fn diff(old: &[&str], new: &[&str]) -> Result<Vec<Edit>> {
+    let mut edits = Vec::with_capacity(cmp::max(old.len(), new.len()));
+    compare(old, new, 0, 0, &mut edits)?;
+    Ok(edits)
+}
+
+/// Appends the edits turning `old` into `new` to `edits`, where the slices
+/// start at old line `x0` and new line `y0`.
+fn compare(old: &[&str], new: &[&str], x0: usize, y0: usize, edits: &mut Vec<Edit>) -> Result<()> {
+    // lines common to the start and the end are kept as they are
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    let (old_mid, new_mid) = (&old[prefix..old_end], &new[prefix..new_end]);
+    let (x0_mid, y0_mid) = (x0 + prefix, y0 + prefix);
+
+    edits.extend((0..prefix).map(|i| Edit::Keep(x0 + i, y0 + i)));
+
+    if old_mid.is_empty() {
+        edits.extend((0..new_mid.len()).map(|j| Edit::Insert(y0_mid + j)));
+    } else if new_mid.is_empty() {
+        edits.extend((0..old_mid.len()).map(|i| Edit::Delete(x0_mid + i)));
+    } else {
+        // with the ends stripped there are at least two differences, so
+        // both halves have fewer than the whole
+        let (x, y, u, v) = middle_snake(old_mid, new_mid)?;
+        compare(&old_mid[..x], &new_mid[..y], x0_mid, y0_mid, edits)?;
+        edits.extend((0..u - x).map(|i| Edit::Keep(x0_mid + x + i, y0_mid + y + i)));
+        compare(&old_mid[u..], &new_mid[v..], x0_mid + u, y0_mid + v, edits)?;
+    }
+
+    edits.extend((0..suffix).map(|i| Edit::Keep(old_end + x0 + i, new_end + y0 + i)));
+    Ok(())
+}
+
+/// Finds the middle snake of a shortest path from the start to the end of
+/// `old` and `new`, searching from both ends at once until the paths meet.
+/// Returns the snake as its start `(x, y)` and end `(u, v)`.
+fn middle_snake(old: &[&str], new: &[&str]) -> Result<(usize, usize, usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+
+    // furthest x reached on each diagonal k = x - y, indexed by k + offset;
+    // backwards x and y count from the ends
+    let mut forward = vec![-1isize; 2 * offset as usize + 1];
+    let mut backward = vec![-1isize; 2 * offset as usize + 1];
+    forward[offset as usize + 1] = 0;
+    backward[offset as usize + 1] = 0;
+
+    for d in 0..max + 1 {
+        signal::check_interrupt()?;
+
+        let mut k = -d;
+        while k <= d {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && forward[i - 1] < forward[i + 1]) { forward[i + 1] } else { forward[i - 1] + 1 };
+            let mut y = x - k;
+            let (start_x, start_y) = (x, y);
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+
+            // the backward paths of the previous step are d - 1 long, paths
+            // that ran off the edges can't meet
+            let back_k = delta - k;
+            if odd && back_k > -d && back_k < d && x <= n && y <= m {
+                let back_x = backward[(back_k + offset) as usize];
+                if back_x >= 0 && back_x <= n && back_x - back_k <= m && x + back_x >= n {
+                    return Ok((start_x as usize, start_y as usize, x as usize, y as usize));
+                }
+            }
+
+            k += 2;
+        }
+
+        let mut k = -d;
+        while k <= d {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && backward[i - 1] < backward[i + 1]) { backward[i + 1] } else { backward[i - 1] + 1 };
+            let mut y = x - k;
+            let (start_x, start_y) = (x, y);
+
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+
+            let forward_k = delta - k;
+            if !odd && forward_k >= -d && forward_k <= d && x <= n && y <= m {
+                let forward_x = forward[(forward_k + offset) as usize];
+                if forward_x >= 0 && forward_x <= n && forward_x - forward_k <= m && forward_x + x >= n {
+                    return Ok(((n - x) as usize, (m - y) as usize, (n - start_x) as usize, (m - start_y) as usize));
+                }
+            }
+
+            k += 2;
+        }
+    }
+
+    unreachable!()
+}
+
+/// Formats the differences between `old` and `new` as the hunks of a
+/// unified diff, each with up to `context` unchanged lines around it.
+/// Headers naming the files are left to the caller.
+pub fn unified(old: &[&str], new: &[&str], context: usize) -> Result<Vec<String>> {
+    let edits = diff(old, new)?;
+    let changes: Vec<usize> = edits.iter().enumerate()
+        .filter(|&(_, edit)| !matches!(*edit, Edit::Keep(_, _)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut output = Vec::new();
+    let mut first = 0;
+    // old and new lines before the edits counted so far
+    let (mut counted, mut old_start, mut new_start) = (0, 0, 0);
+
+    while first < changes.len() {
+        // changes closer than twice the context share a hunk
+        let mut last = first;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] - 1 <= 2 * context {
+            last += 1;
+        }
+
+        let start = changes[first].saturating_sub(context);
+        let end = cmp::min(changes[last] + context + 1, edits.len());
+        let hunk = &edits[start..end];
+
+        for edit in &edits[counted..start] {
+            match *edit {
+                Edit::Keep(_, _) => { old_start += 1; new_start += 1; },
+                Edit::Delete(_) => old_start += 1,
+                Edit::Insert(_) => new_start += 1
+            }
+        }
+        counted = start;
+        let old_len = hunk.iter().filter(|edit| !matches!(**edit, Edit::Insert(_))).count();
+        let new_len = hunk.iter().filter(|edit| !matches!(**edit, Edit::Delete(_))).count();
+
+        output.push(format!("@@ -{} +{} @@", HunkRange(old_start, old_len), HunkRange(new_start, new_len)));
+        for edit in hunk {
+            output.push(match *edit {
+                Edit::Keep(a, _) => format!(" {}", old[a]),
+                Edit::Delete(a) => format!("-{}", old[a]),
+                Edit::Insert(b) => format!("+{}", new[b])
+            });
+        }
+
+        first = last + 1;
+    }
+
+    Ok(output)
+}
+
+/// `start,len` of a hunk header, where an empty range names the line
+/// before it and a length of one is left out.
+struct HunkRange(usize, usize);
+
+impl fmt::Display for HunkRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HunkRange(start, 0) => write!(f, "{},0", start),
+            HunkRange(start, 1) => write!(f, "{}", start + 1),
+            HunkRange(start, len) => write!(f, "{},{}", start + 1, len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The new lines rebuilt from `old` and the edit script.
+    fn apply<'a>(old: &[&'a str], new: &[&'a str], edits: &[Edit]) -> Vec<&'a str> {
+        edits.iter().filter_map(|edit| match *edit {
+            Edit::Keep(a, _) => Some(old[a]),
+            Edit::Delete(_) => None,
+            Edit::Insert(b) => Some(new[b])
+        }).collect()
+    }
+
+    #[test]
+    fn finds_a_shortest_edit_script() {
+        let old = ["A", "B", "C", "A", "B", "B", "A"];
+        let new = ["C", "B", "A", "B", "A", "C"];
+
+        let edits = diff(&old, &new).unwrap();
+        assert_eq!(apply(&old, &new, &edits), new);
+        assert_eq!(edits.iter().filter(|edit| !matches!(**edit, Edit::Keep(_, _))).count(), 5);
+
+        assert_eq!(diff(&old, &old).unwrap(), (0..7).map(|i| Edit::Keep(i, i)).collect::<Vec<_>>());
+        assert_eq!(diff(&[], &new).unwrap(), (0..6).map(Edit::Insert).collect::<Vec<_>>());
+    }
+
+    /// Length of a shortest edit script, from the longest common
+    /// subsequence.
+    fn distance(old: &[&str], new: &[&str]) -> usize {
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in 0..old.len() {
+            for j in 0..new.len() {
+                lcs[i + 1][j + 1] = if old[i] == new[j] { lcs[i][j] + 1 } else { cmp::max(lcs[i][j + 1], lcs[i + 1][j]) };
+            }
+        }
+        old.len() + new.len() - 2 * lcs[old.len()][new.len()]
+    }
+
+    #[test]
+    fn scripts_are_shortest() {
+        let words = ["a", "b", "c", "d"];
+        let mut seed = 12345u32;
+        let mut random = |len: usize| -> Vec<&str> {
+            (0..len).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                words[(seed >> 16) as usize % words.len()]
+            }).collect()
+        };
+
+        for round in 0..300 {
+            let old = random(round % 23);
+            let new = random(round % 17);
+
+            let edits = diff(&old, &new).unwrap();
+            assert_eq!(apply(&old, &new, &edits), new, "{:?} -> {:?}", old, new);
+            let changes = edits.iter().filter(|edit| !matches!(**edit, Edit::Keep(_, _))).count();
+            assert_eq!(changes, distance(&old, &new), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn long_inputs_with_many_differences() {
+        let old: Vec<String> = (0..6000).map(|n| (n % 7).to_string()).collect();
+        let new: Vec<String> = (0..6000).map(|n| (n % 5).to_string()).collect();
+        let old: Vec<&str> = old.iter().map(|line| line.as_str()).collect();
+        let new: Vec<&str> = new.iter().map(|line| line.as_str()).collect();
+
+        let edits = diff(&old, &new).unwrap();
+        assert_eq!(apply(&old, &new, &edits), new);
+    }
+
+    #[test]
+    fn groups_changes_into_hunks() {
+        let old = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        let new = ["a", "B", "c", "d", "e", "f", "g", "h", "I", "j"];
+
+        assert_eq!(unified(&old, &new, 1).unwrap(), &[
+            "@@ -1,3 +1,3 @@", " a", "-b", "+B", " c",
+            "@@ -8,3 +8,3 @@", " h", "-i", "+I", " j"
+        ]);
+
+        // close enough to share the context between them
+        let hunks = unified(&old, &new, 3).unwrap();
+        assert_eq!(hunks[0], "@@ -1,10 +1,10 @@");
+        assert_eq!(hunks.len(), 13);
+
+        assert!(unified(&old, &old, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn names_the_line_before_empty_ranges() {
+        assert_eq!(unified(&[], &["x", "y"], 3).unwrap(), &["@@ -0,0 +1,2 @@", "+x", "+y"]);
+        assert_eq!(unified(&["a", "b"], &["a"], 0).unwrap(), &["@@ -2 +1,0 @@", "-b"]);
+    }
+}
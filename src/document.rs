@@ -0,0 +1,513 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{
+    HashMap,
+    VecDeque
+};
+
+use encoding_rs::Encoding;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{
+    Read,
+    Seek,
+    SeekFrom
+};
+use std::ops;
+use std::path::Path;
+
+use buffer::{
+    Buffer,
+    Lines
+};
+use charset::Charset;
+use codec::{
+    self,
+    Codec
+};
+use highlight::StateCache;
+use pos;
+use signal;
+use stamp::{
+    FileStamp,
+    Hashing
+};
+use swap::Swap;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// A buffer together with the editing state that belongs to it: the current
+/// line, the file it was read from, how that file was compressed and
+/// encoded, and its marks.
+///
+/// Every edit bumps the revision of the document, which tells its swap
+/// file whether it is out of date.
+#[derive(Debug)]
+pub struct Document {
+    buffer: Buffer,
+    current_line: usize,
+    file_name: Option<OsString>,
+    codec: Codec,
+    charset: Charset,
+    marks: HashMap<char, usize>,
+    /// Lines still to be visited by commands running over several lines,
+    /// innermost last.
+    visits: Vec<VecDeque<usize>>,
+    revision: usize,
+    swap: Option<Swap>,
+    stamp: Option<FileStamp>,
+    highlight_states: RefCell<StateCache>
+}
+
+/// Reads the first bytes of `file` and rewinds it.
+fn read_head(file: &mut File) -> Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(codec::MAGIC_LEN);
+    file.by_ref().take(codec::MAGIC_LEN as u64).read_to_end(&mut head)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok(head)
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Document {
+
+    pub fn new() -> Document {
+        Document::from_buffer(Buffer::new(), None)
+    }
+
+    pub fn from_buffer(buffer: Buffer, file_name: Option<OsString>) -> Document {
+        Document {
+            buffer,
+            current_line: 1,
+            file_name,
+            codec: Codec::Plain,
+            charset: Charset::utf8(),
+            marks: HashMap::new(),
+            visits: Vec::new(),
+            revision: 0,
+            swap: None,
+            stamp: None,
+            highlight_states: RefCell::new(StateCache::new())
+        }
+    }
+
+    /// Reads the file at `path`, decompressing it if it is compressed.
+    ///
+    /// The text is decoded according to its byte order mark, or else as
+    /// `encoding`, which defaults to UTF-8.
+    pub fn open<P: AsRef<Path>>(path: P, encoding: Option<&'static Encoding>) -> Result<Document> {
+        let file_name = path.as_ref().as_os_str().to_os_string();
+        let mut file = File::open(&path)?;
+        let codec = Codec::detect(&path, &read_head(&mut file)?);
+
+        // reading a big file can be interrupted
+        let mut file = Hashing::new(file);
+        let mut bytes = Vec::new();
+        codec.decoder(signal::Interruptible::new(&mut file))?.read_to_end(&mut bytes)?;
+        // the hash covers what a decoder didn't need to read as well
+        io::copy(&mut file, &mut io::sink())?;
+        let stamp = FileStamp::new(path.as_ref(), Some(file.hash()))?;
+
+        let (charset, text) = Charset::decode(&bytes, encoding)?;
+        let buffer = Buffer::from_buf_read(io::Cursor::new(text))?;
+
+        let mut document = Document::from_buffer(buffer, Some(file_name));
+        document.codec = codec;
+        document.charset = charset;
+        document.stamp = Some(stamp);
+        Ok(document)
+    }
+
+    /// Memory maps the file at `path`. Only uncompressed UTF-8 files
+    /// without a byte order mark can be mapped, others are read like in
+    /// `open`.
+    pub fn open_mapped<P: AsRef<Path>>(path: P) -> Result<Document> {
+        let file_name = path.as_ref().as_os_str().to_os_string();
+        let mut file = File::open(&path)?;
+        let head = read_head(&mut file)?;
+
+        if Codec::detect(&path, &head) != Codec::Plain || Encoding::for_bom(&head).is_some() {
+            return Document::open(path, None);
+        }
+
+        // hashing would read the whole file, which mapping it avoids
+        let stamp = FileStamp::new(path.as_ref(), None)?;
+        let buffer = Buffer::map_file(path)?;
+
+        let mut document = Document::from_buffer(buffer, Some(file_name));
+        document.stamp = Some(stamp);
+        Ok(document)
+    }
+
+    /// Writes the buffer to `path`, compressed with `codec`.
+    ///
+    /// All of the text is encoded in memory first, so a line that can't be
+    /// encoded leaves the file as it was. The file is then
+    /// overwritten in place, which keeps its links, owner and permissions.
+    /// A mapped buffer is read into memory before that, as its file may be
+    /// the one overwritten.
+    pub fn write<P: AsRef<Path>>(&mut self, path: P, codec: Codec) -> Result<()> {
+        let path = path.as_ref();
+        self.buffer.check_mapping()?;
+
+        let (bytes, hash) = self.encode(codec)?;
+        // lines read from a mapped file are validated while they are encoded
+        self.buffer.check_mapping()?;
+        self.buffer.unmap();
+
+        let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        io::Write::write_all(&mut file, &bytes)?;
+
+        self.buffer.set_unmodified();
+        self.remove_swap();
+        self.codec = codec;
+        self.stamp = Some(FileStamp::new(path, Some(hash))?);
+        Ok(())
+    }
+
+    /// The buffer as it is written to a file, along with the hash of it.
+    fn encode(&self, codec: Codec) -> Result<(Vec<u8>, u64)> {
+        let encoder = codec.encoder(Hashing::new(Vec::new()))?;
+        let mut transcoder = self.charset.encoder(encoder);
+
+        if let Err(e) = self.buffer.write(&mut transcoder) {
+            return Err(transcoder.take_error().unwrap_or(e));
+        }
+
+        let bytes = transcoder.into_inner().finish()?;
+        let hash = bytes.hash();
+        Ok((bytes.into_inner(), hash))
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    /// Changes the encoding the buffer is written with.
+    pub fn set_charset(&mut self, charset: Charset) {
+        if charset != self.charset {
+            self.charset = charset;
+            self.buffer.set_modified();
+            self.revision += 1;
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn file_name(&self) -> Option<&OsString> {
+        self.file_name.as_ref()
+    }
+
+    /// Names the file of the document, which may change how its syntax is
+    /// highlighted.
+    pub fn set_file_name(&mut self, file_name: OsString) {
+        self.file_name = Some(file_name);
+        self.highlight_states.get_mut().clear();
+    }
+
+    pub fn current_line(&self) -> usize {
+        self.current_line
+    }
+
+    pub fn set_current_line(&mut self, line: usize) {
+        self.current_line = line;
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.buffer.has_changes()
+    }
+
+    pub fn check_mapping(&self) -> Result<()> {
+        self.buffer.check_mapping()
+    }
+
+    pub fn lines(&self, range: &ops::Range<usize>) -> Lines<'_> {
+        self.buffer.lines(range)
+    }
+
+    /// States of the syntax highlighter at lines of the buffer, kept up to
+    /// date with edits.
+    pub fn highlight_states(&self) -> &RefCell<StateCache> {
+        &self.highlight_states
+    }
+
+    pub fn set_mark(&mut self, mark: char, line: usize) {
+        self.marks.insert(mark, line);
+    }
+
+    /// Whether the file the document was read from or last written to was
+    /// changed on disk since then.
+    pub fn is_changed_on_disk(&self) -> Result<bool> {
+        match (self.file_name.as_ref(), self.stamp.as_ref()) {
+            (Some(file_name), Some(stamp)) => stamp.is_changed(Path::new(file_name)),
+            _ => Ok(false)
+        }
+    }
+
+    /// Replaces the whole text, as recovered from a swap file. Marks are
+    /// dropped and the document counts as modified.
+    pub fn replace_buffer(&mut self, mut buffer: Buffer) {
+        buffer.set_modified();
+        self.buffer = buffer;
+        self.marks.clear();
+        for lines in &mut self.visits {
+            lines.clear();
+        }
+        self.current_line = cmp::min(self.current_line, self.buffer.len());
+        self.revision += 1;
+        self.highlight_states.get_mut().clear();
+    }
+
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    pub fn set_swap(&mut self, swap: Option<Swap>) {
+        self.swap = swap;
+    }
+
+    /// Brings the swap file up to date: it is written if the buffer has
+    /// unsaved changes and removed otherwise. Unless `flush` is set, writes
+    /// soon after the last one are left for later.
+    pub fn update_swap(&mut self, flush: bool) -> Result<()> {
+        let has_changes = self.has_changes();
+
+        match self.swap {
+            Some(ref mut swap) if has_changes && flush => swap.flush(&self.buffer, self.revision),
+            Some(ref mut swap) if has_changes => swap.update(&self.buffer, self.revision),
+            Some(ref mut swap) => {
+                swap.remove();
+                Ok(())
+            },
+            None => Ok(())
+        }
+    }
+
+    /// Removes the swap file, the changes it kept were written or are
+    /// discarded. Later changes are journaled again.
+    pub fn remove_swap(&mut self) {
+        if let Some(ref mut swap) = self.swap {
+            swap.remove();
+        }
+    }
+
+    /// Names of the marks set in this document, in order.
+    pub fn mark_names(&self) -> Vec<char> {
+        let mut names: Vec<char> = self.marks.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Starts visiting `lines`, in order. Like marks, they follow the text
+    /// as lines are inserted and deleted, and deleted ones aren't visited.
+    /// Visits nest, the lines come from the one started last.
+    pub fn start_visit<I: IntoIterator<Item=usize>>(&mut self, lines: I) {
+        self.visits.push(lines.into_iter().collect());
+    }
+
+    /// The next line of the innermost visit, if any is left.
+    pub fn next_visited(&mut self) -> Option<usize> {
+        self.visits.last_mut().and_then(|lines| lines.pop_front())
+    }
+
+    pub fn end_visit(&mut self) {
+        self.visits.pop();
+    }
+
+    /// Inserts `lines` after line `pos`, shifting marks below it.
+    pub fn insert_lines<I: IntoIterator<Item=String>>(&mut self, pos: usize, lines: I) -> usize {
+        let len_before = self.buffer.len();
+        self.buffer.insert_lines(pos, lines);
+        let count = self.buffer.len() - len_before;
+        self.revision += 1;
+        self.highlight_states.get_mut().invalidate_from(pos);
+
+        for line in self.marks.values_mut().chain(self.visits.iter_mut().flatten()) {
+            if *line > pos {
+                *line += count;
+            }
+        }
+
+        count
+    }
+
+    /// Replaces the text of the line at `index`, marks on it stay.
+    pub fn replace_line(&mut self, index: usize, line: String) {
+        self.buffer.replace_line(index, line);
+        self.revision += 1;
+        self.highlight_states.get_mut().invalidate_from(index);
+    }
+
+    /// Removes the lines in `range`, dropping marks and visited lines that
+    /// pointed into it.
+    pub fn delete_lines(&mut self, range: &ops::Range<usize>) {
+        self.buffer.delete_lines(range.start, range.end);
+        self.revision += 1;
+        self.highlight_states.get_mut().invalidate_from(range.start);
+
+        let count = range.end - range.start;
+        let mut deleted = Vec::new();
+
+        for (mark, line) in self.marks.iter_mut() {
+            if *line > range.end {
+                *line -= count;
+            } else if *line > range.start {
+                deleted.push(*mark);
+            }
+        }
+
+        for mark in deleted {
+            self.marks.remove(&mark);
+        }
+
+        for lines in &mut self.visits {
+            lines.retain(|&line| line <= range.start || line > range.end);
+            for line in lines.iter_mut() {
+                if *line > range.end {
+                    *line -= count;
+                }
+            }
+        }
+    }
+
+}
+
+impl pos::Converter<&pos::Pos, Result<usize>> for Document {
+    fn convert(&self, pos: &pos::Pos) -> Result<usize> {
+        match *pos {
+            pos::Pos::Line(n) => Ok(n),
+            pos::Pos::Current => Ok(self.current_line),
+            pos::Pos::End => Ok(self.buffer.len()),
+            pos::Pos::Mark(c) => self.marks.get(&c).cloned().ok_or(Error::new(ErrorType::UnknownMark(c))),
+            pos::Pos::Search(_, _) => Err(Error::unknown("search addresses are resolved by Rsed"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swap;
+    use testutil::TempFile;
+
+    #[test]
+    fn failed_write_keeps_the_file() {
+        let temp = TempFile::new("document-latin1", b"line1\nline2\n");
+        let mut document = Document::open(temp.path(), Some(encoding_rs::WINDOWS_1252)).unwrap();
+
+        document.insert_lines(1, vec!["漢".to_string()]);
+        assert!(document.write(temp.path(), Codec::Plain).is_err());
+        assert_eq!(temp.contents(), b"line1\nline2\n");
+        assert!(document.has_changes());
+
+        document.replace_line(1, "é".to_string());
+        document.write(temp.path(), Codec::Plain).unwrap();
+        assert_eq!(temp.contents(), b"line1\n\xe9\nline2\n");
+        assert!(!document.has_changes());
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let temp = TempFile::new("document-crlf", b"one\r\ntwo\r\n");
+        let mut document = Document::open(temp.path(), None).unwrap();
+        assert_eq!(document.lines(&(0..2)).collect::<Vec<_>>(), &["one", "two"]);
+
+        document.insert_lines(1, vec!["new".to_string()]);
+        document.write(temp.path(), Codec::Plain).unwrap();
+        assert_eq!(temp.contents(), b"one\r\nnew\r\ntwo\r\n");
+
+        let mut document = Document::open_mapped(temp.path()).unwrap();
+        document.delete_lines(&(1..2));
+        document.write(temp.path(), Codec::Plain).unwrap();
+        assert_eq!(temp.contents(), b"one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn round_trips_utf16_with_a_bom() {
+        let contents = b"\xff\xfeo\x00n\x00e\x00\r\x00\n\x00\x34\x6c\r\x00\n\x00";
+        let temp = TempFile::new("document-utf16", contents);
+        let mut document = Document::open(temp.path(), None).unwrap();
+        assert_eq!(document.charset(), Charset::new(encoding_rs::UTF_16LE, true));
+        assert_eq!(document.lines(&(0..2)).collect::<Vec<_>>(), &["one", "水"]);
+
+        document.write(temp.path(), Codec::Plain).unwrap();
+        assert_eq!(temp.contents(), &contents[..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_in_place() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempFile::new("document-in-place", b"one\ntwo\n");
+        let link = temp.path().with_extension("link");
+        let _ = fs::remove_file(&link);
+        fs::hard_link(temp.path(), &link).unwrap();
+        fs::set_permissions(temp.path(), fs::Permissions::from_mode(0o640)).unwrap();
+
+        // the mapped file itself is overwritten
+        let mut document = Document::open_mapped(temp.path()).unwrap();
+        document.delete_lines(&(0..1));
+        document.write(temp.path(), Codec::Plain).unwrap();
+        assert_eq!(document.lines(&(0..1)).collect::<Vec<_>>(), &["two"]);
+
+        assert_eq!(fs::read(&link).unwrap(), b"two\n");
+        assert_eq!(fs::metadata(temp.path()).unwrap().permissions().mode() & 0o777, 0o640);
+        fs::remove_file(&link).unwrap();
+    }
+
+    #[test]
+    fn swap_outlives_the_document() {
+        let temp = TempFile::new("document-swap", b"one\ntwo\nthree\n");
+        let swap_path = swap::swap_path(temp.path());
+
+        let mut document = Document::open(temp.path(), None).unwrap();
+        document.set_swap(Some(Swap::new(temp.path())));
+
+        document.delete_lines(&(0..1));
+        document.update_swap(false).unwrap();
+        assert_eq!(fs::read(&swap_path).unwrap(), b"two\nthree\n");
+
+        // too soon after the last write, unless flushed
+        document.delete_lines(&(0..1));
+        document.update_swap(false).unwrap();
+        assert_eq!(fs::read(&swap_path).unwrap(), b"two\nthree\n");
+        document.update_swap(true).unwrap();
+        assert_eq!(fs::read(&swap_path).unwrap(), b"three\n");
+
+        drop(document);
+        assert!(swap_path.exists());
+
+        let mut document = Document::open(temp.path(), None).unwrap();
+        document.set_swap(Some(Swap::new(temp.path())));
+        document.delete_lines(&(0..1));
+        document.update_swap(false).unwrap();
+        document.write(temp.path(), Codec::Plain).unwrap();
+        assert!(!swap_path.exists());
+    }
+}
@@ -0,0 +1,377 @@
+use std::env;
+use std::fs;
+use std::fs::{
+    File,
+    OpenOptions
+};
+use std::io;
+use std::io::{
+    BufRead,
+    BufReader,
+    Write
+};
+use std::path::PathBuf;
+
+use cmd;
+use term::{
+    Key,
+    RawMode,
+    TerminalInput
+};
+use term;
+use Result;
+
+/// Number of entries kept in the history.
+const MAX_HISTORY: usize = 1000;
+
+/// Time without a key press after which `read_line` calls its idle hook, in
+/// milliseconds.
+const IDLE_TIMEOUT: i32 = 500;
+
+/// Completes the word in front of the cursor.
+pub trait Completer {
+    /// Returns the byte offset at which the word at the end of `line`
+    /// starts, along with the possible completions of that word.
+    fn complete(&self, line: &str) -> (usize, Vec<String>);
+}
+
+/// Completes file names after `e`, `w`, `B` and `:diff`, and mark names
+/// after `'`.
+pub struct CommandCompleter {
+    marks: Vec<char>
+}
+
+impl CommandCompleter {
+    pub fn new(marks: Vec<char>) -> CommandCompleter {
+        CommandCompleter {
+            marks
+        }
+    }
+}
+
+impl Completer for CommandCompleter {
+    fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        if line.ends_with('\'') {
+            return (line.len(), self.marks.iter().map(|c| c.to_string()).collect());
+        }
+
+        if let Some(start) = cmd::file_name_start(line) {
+            return (start, complete_file_name(&line[start..]));
+        }
+
+        (line.len(), Vec::new())
+    }
+}
+
+fn complete_file_name(word: &str) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(n) => word.split_at(n + 1),
+        None => ("", word)
+    };
+
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new()
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => return None
+            };
+
+            // hidden files only when asked for
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            let is_dir = fs::metadata(entry.path()).map(|m| m.is_dir()).unwrap_or(false);
+            Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+
+    names.sort();
+    names
+}
+
+fn common_prefix(words: &[String]) -> String {
+    let mut prefix = match words.first() {
+        Some(word) => word.clone(),
+        None => return String::new()
+    };
+
+    for word in &words[1..] {
+        let len = prefix.char_indices()
+            .zip(word.chars())
+            .find(|&((_, a), b)| a != b)
+            .map_or(prefix.len().min(word.len()), |((n, _), _)| n);
+        prefix.truncate(len);
+    }
+
+    prefix
+}
+
+/// Line being edited and the position of the cursor in it.
+struct Edit {
+    chars: Vec<char>,
+    cursor: usize
+}
+
+impl Edit {
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().cloned().collect()
+    }
+
+    fn insert(&mut self, text: &str) {
+        for c in text.chars() {
+            self.chars.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    /// Removes the characters from `start` up to the cursor.
+    fn delete_back_to(&mut self, start: usize) {
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    fn word_start(&self) -> usize {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        start
+    }
+}
+
+/// Line editor for interactive input.
+///
+/// Supports the cursor keys, Home and End, Ctrl-A/E/B/F to move, Ctrl-W/U/K
+/// to delete a word, to the start or to the end of the line, a history
+/// browsed with the up and down keys or Ctrl-P/N, and completion on Tab.
+/// The history is kept in a file across sessions.
+pub struct LineEditor {
+    history: Vec<String>,
+    history_file: Option<PathBuf>
+}
+
+impl LineEditor {
+
+    /// Creates an editor whose history is loaded from and saved to
+    /// `history_file`.
+    pub fn new(history_file: Option<PathBuf>) -> LineEditor {
+        let mut history = Vec::new();
+
+        if let Some(file) = history_file.as_ref().and_then(|path| File::open(path).ok()) {
+            history.extend(BufReader::new(file).lines().map_while(|line| line.ok()));
+        }
+
+        if history.len() > MAX_HISTORY {
+            let excess = history.len() - MAX_HISTORY;
+            history.drain(..excess);
+
+            // keep the file from growing forever
+            if let Some(ref path) = history_file {
+                let _ = File::create(path).and_then(|mut file| {
+                    history.iter().try_for_each(|line| writeln!(file, "{}", line))
+                });
+            }
+        }
+
+        LineEditor {
+            history,
+            history_file
+        }
+    }
+
+    /// `~/.rsed_history`
+    pub fn default_history_file() -> Option<PathBuf> {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".rsed_history"))
+    }
+
+    /// Adds `line` to the history, unless it is empty or repeats the last
+    /// entry.
+    pub fn add_history(&mut self, line: &str) {
+        if line.is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+
+        // losing the history isn't worth interrupting the user for
+        if let Some(ref path) = self.history_file {
+            let _ = OpenOptions::new().create(true).append(true).open(path).and_then(|mut file| {
+                writeln!(file, "{}", line)
+            });
+        }
+    }
+
+    /// Reads a line from the terminal, returning it with a trailing newline
+    /// like `BufRead::read_line`, or `None` on Ctrl-D at an empty line.
+    ///
+    /// Without a completer Tab inserts a tab. The first time no key is
+    /// pressed for a moment `idle` is called.
+    pub fn read_line(&mut self, prompt: &str, completer: Option<&dyn Completer>, idle: &mut dyn FnMut()) -> Result<Option<String>> {
+        let _raw = RawMode::enable()?;
+
+        let mut input = TerminalInput;
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+
+        let mut edit = Edit {
+            chars: Vec::new(),
+            cursor: 0
+        };
+        let mut history_index = self.history.len();
+        let mut draft = String::new();
+
+        refresh(&mut out, prompt, &edit)?;
+        let mut was_idle = false;
+
+        loop {
+            if !was_idle && !term::input_pending(IDLE_TIMEOUT) {
+                idle();
+                was_idle = true;
+            }
+
+            let key = match term::read_key(&mut input) {
+                Ok(Some(key)) => key,
+                Ok(None) => return Ok(None),
+                Err(e) => {
+                    // interrupted, leave the abandoned line behind
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Err(e);
+                }
+            };
+
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(Some(edit.text() + "\n"));
+                },
+                Key::Ctrl('d') if edit.chars.is_empty() => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(None);
+                },
+                Key::Ctrl('a') | Key::Home => edit.cursor = 0,
+                Key::Ctrl('e') | Key::End => edit.cursor = edit.chars.len(),
+                Key::Ctrl('b') | Key::Left => edit.cursor = edit.cursor.saturating_sub(1),
+                Key::Ctrl('f') | Key::Right if edit.cursor < edit.chars.len() => edit.cursor += 1,
+                Key::Backspace if edit.cursor > 0 => {
+                    let start = edit.cursor - 1;
+                    edit.delete_back_to(start);
+                },
+                Key::Ctrl('d') | Key::Delete if edit.cursor < edit.chars.len() => {
+                    edit.chars.remove(edit.cursor);
+                },
+                Key::Ctrl('w') => {
+                    let start = edit.word_start();
+                    edit.delete_back_to(start);
+                },
+                Key::Ctrl('u') => edit.delete_back_to(0),
+                Key::Ctrl('k') => edit.chars.truncate(edit.cursor),
+                Key::Ctrl('l') => write!(out, "\x1b[H\x1b[2J")?,
+                Key::Ctrl('p') | Key::Up if history_index > 0 => {
+                    if history_index == self.history.len() {
+                        draft = edit.text();
+                    }
+                    history_index -= 1;
+                    edit.set(&self.history[history_index]);
+                },
+                Key::Ctrl('n') | Key::Down if history_index < self.history.len() => {
+                    history_index += 1;
+                    if history_index == self.history.len() {
+                        edit.set(&draft);
+                    } else {
+                        edit.set(&self.history[history_index]);
+                    }
+                },
+                Key::Tab => match completer {
+                    Some(completer) => complete(&mut out, &mut edit, completer)?,
+                    None => edit.insert("\t")
+                },
+                Key::Char(c) => edit.insert(c.encode_utf8(&mut [0; 4])),
+                _ => ()
+            }
+
+            refresh(&mut out, prompt, &edit)?;
+        }
+    }
+
+}
+
+/// Redraws the line being edited and puts the cursor in place.
+fn refresh<W: Write>(out: &mut W, prompt: &str, edit: &Edit) -> Result<()> {
+    write!(out, "\r{}{}\x1b[K", prompt, edit.text())?;
+
+    let back = edit.chars.len() - edit.cursor;
+    if back > 0 {
+        write!(out, "\x1b[{}D", back)?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// Completes the word in front of the cursor as far as it is unambiguous,
+/// listing the candidates if that doesn't get any further.
+fn complete<W: Write>(out: &mut W, edit: &mut Edit, completer: &dyn Completer) -> Result<()> {
+    let before: String = edit.chars[..edit.cursor].iter().cloned().collect();
+    let (start, candidates) = completer.complete(&before);
+    let word = &before[start..];
+
+    if candidates.is_empty() {
+        write!(out, "\x07")?;
+        return Ok(());
+    }
+
+    let prefix = common_prefix(&candidates);
+    if prefix.len() > word.len() && prefix.starts_with(word) {
+        edit.insert(&prefix[word.len()..]);
+    } else if candidates.len() > 1 {
+        write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+    } else {
+        write!(out, "\x07")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_by_command() {
+        let completer = CommandCompleter::new(vec!['a', 'b']);
+
+        assert_eq!(completer.complete("1,'"), (3, vec!["a".to_string(), "b".to_string()]));
+        assert!(completer.complete("1,$w /").1.contains(&"/tmp/".to_string()));
+        assert!(completer.complete("B /").1.contains(&"/tmp/".to_string()));
+        assert!(completer.complete("w! /").1.contains(&"/tmp/".to_string()));
+        assert!(completer.complete(":diff -U 1 /").1.contains(&"/tmp/".to_string()));
+        assert_eq!(completer.complete(":diff -U 1 /t").0, 11);
+        // r isn't a command
+        assert_eq!(completer.complete("r /"), (3, Vec::new()));
+        // commands ending in e or w aren't either
+        assert_eq!(completer.complete("s/x/name /"), (10, Vec::new()));
+        assert_eq!(completer.complete("g/re/w /"), (8, Vec::new()));
+        assert_eq!(completer.complete(":reload /"), (9, Vec::new()));
+    }
+}
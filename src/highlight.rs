@@ -0,0 +1,571 @@
+use std::cmp;
+use std::fmt;
+use std::ops;
+use std::path::Path;
+
+use buffer::Buffer;
+
+/// Number of lines between two states recorded by a `StateCache`.
+const STATE_INTERVAL: usize = 256;
+
+/// Kind of syntax element a token is.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Style {
+    Comment,
+    String,
+    Keyword,
+    Number,
+    Key,
+    Variable
+}
+
+/// A styled byte range of a line.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub span: ops::Range<usize>,
+    pub style: Style
+}
+
+impl Token {
+    pub fn new(span: ops::Range<usize>, style: Style) -> Token {
+        Token {
+            span,
+            style
+        }
+    }
+}
+
+/// Construct a line ends in, carried over to the next line.
+#[derive(Debug, PartialEq, Clone)]
+pub enum State {
+    Normal,
+    /// Inside a block comment, nested this deep.
+    Comment(usize),
+    /// Inside a string closed by the given delimiter, along with whether
+    /// backslash escapes apply.
+    Str(String, bool)
+}
+
+/// Splits lines into styled tokens.
+///
+/// Lines are highlighted in order, each starting in the state the previous
+/// one ended in; to print a range that starts in the middle of a file the
+/// lines before it have to be run through the highlighter first, which a
+/// `StateCache` keeps short.
+pub trait Highlighter {
+    /// Returns the tokens of `line` in order, `state` is the state at the
+    /// start of the line and is updated to the state at its end.
+    fn highlight_line(&self, line: &str, state: &mut State) -> Vec<Token>;
+}
+
+impl fmt::Debug for dyn Highlighter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Highlighter")
+    }
+}
+
+/// The states every 256th line of a buffer starts in, recorded as they are
+/// reached, so that highlighting from a line only needs to run through the
+/// lines since the closest recorded one.
+///
+/// The states hold for one highlighter and one text: edits have to forget
+/// the states after them, and a different highlighter all of them.
+#[derive(Debug, Default)]
+pub struct StateCache {
+    states: Vec<State>
+}
+
+impl StateCache {
+
+    pub fn new() -> StateCache {
+        StateCache {
+            states: Vec::new()
+        }
+    }
+
+    /// Forgets the states that depend on the line at `index`.
+    pub fn invalidate_from(&mut self, index: usize) {
+        self.states.truncate(index / STATE_INTERVAL + 1);
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    /// Returns the state the line at `index` of `buffer` starts in.
+    pub fn state_at(&mut self, highlighter: &dyn Highlighter, buffer: &Buffer, index: usize) -> State {
+        if self.states.is_empty() {
+            self.states.push(State::Normal);
+        }
+
+        let known = cmp::min(index / STATE_INTERVAL, self.states.len() - 1);
+        let mut state = self.states[known].clone();
+        let mut next = known * STATE_INTERVAL;
+
+        for line in buffer.lines(&(next..index)) {
+            highlighter.highlight_line(line, &mut state);
+            next += 1;
+
+            if next.is_multiple_of(STATE_INTERVAL) && next / STATE_INTERVAL == self.states.len() {
+                self.states.push(state.clone());
+            }
+        }
+
+        state
+    }
+
+}
+
+/// Returns the built-in highlighter for the type of the file at `path`.
+/// Needs the `syntax` feature, without it this is always `None`.
+#[cfg(feature = "syntax")]
+pub fn for_path<P: AsRef<Path>>(path: P) -> Option<&'static dyn Highlighter> {
+    let syntax: &'static Syntax = match path.as_ref().extension().and_then(|e| e.to_str()) {
+        Some("rs") => &RUST,
+        Some("toml") => &TOML,
+        Some("yml") | Some("yaml") => &YAML,
+        Some("sh") | Some("bash") => &SHELL,
+        _ => return None
+    };
+
+    Some(syntax)
+}
+
+#[cfg(not(feature = "syntax"))]
+pub fn for_path<P: AsRef<Path>>(_path: P) -> Option<&'static dyn Highlighter> {
+    None
+}
+
+/// Delimiters of a kind of string literal.
+#[cfg(feature = "syntax")]
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    escapes: bool,
+    multiline: bool
+}
+
+/// A Rust literal that needs more than the quotes of `Syntax`.
+#[cfg(feature = "syntax")]
+enum Literal {
+    /// A char literal ending at the given offset.
+    Char(usize),
+    /// A raw string closed by the given delimiter, its contents starting at
+    /// the given offset.
+    RawString(String, usize)
+}
+
+/// Table driven description of a language, enough for lightweight
+/// colouring.
+#[cfg(feature = "syntax")]
+struct Syntax {
+    line_comment: Option<&'static str>,
+    /// Whether a line comment has to start a word, as `#` in shell.
+    comment_starts_word: bool,
+    block_comment: Option<(&'static str, &'static str)>,
+    nested_comments: bool,
+    quotes: &'static [Quote],
+    /// Rust's `r#"..."#` strings and `'c'` chars that aren't lifetimes.
+    rust_literals: bool,
+    keywords: &'static [&'static str],
+    /// Separator after a key at the start of a line, `=` in TOML.
+    key_separator: Option<char>,
+    /// `[table]` headers as in TOML.
+    tables: bool,
+    /// `$name` and `${...}` expansions as in shell.
+    variables: bool
+}
+
+#[cfg(feature = "syntax")]
+static RUST: Syntax = Syntax {
+    line_comment: Some("//"),
+    comment_starts_word: false,
+    block_comment: Some(("/*", "*/")),
+    nested_comments: true,
+    quotes: &[
+        Quote { open: "b\"", close: "\"", escapes: true, multiline: true },
+        Quote { open: "\"", close: "\"", escapes: true, multiline: true }
+    ],
+    rust_literals: true,
+    keywords: &[
+        "as", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false",
+        "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+        "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+        "unsafe", "use", "where", "while"
+    ],
+    key_separator: None,
+    tables: false,
+    variables: false
+};
+
+#[cfg(feature = "syntax")]
+static TOML: Syntax = Syntax {
+    line_comment: Some("#"),
+    comment_starts_word: false,
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        Quote { open: "\"\"\"", close: "\"\"\"", escapes: true, multiline: true },
+        Quote { open: "'''", close: "'''", escapes: false, multiline: true },
+        Quote { open: "\"", close: "\"", escapes: true, multiline: false },
+        Quote { open: "'", close: "'", escapes: false, multiline: false }
+    ],
+    rust_literals: false,
+    keywords: &["true", "false"],
+    key_separator: Some('='),
+    tables: true,
+    variables: false
+};
+
+#[cfg(feature = "syntax")]
+static YAML: Syntax = Syntax {
+    line_comment: Some("#"),
+    comment_starts_word: true,
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        Quote { open: "\"", close: "\"", escapes: true, multiline: true },
+        Quote { open: "'", close: "'", escapes: false, multiline: true }
+    ],
+    rust_literals: false,
+    keywords: &["true", "false", "yes", "no", "on", "off", "null"],
+    key_separator: Some(':'),
+    tables: false,
+    variables: false
+};
+
+#[cfg(feature = "syntax")]
+static SHELL: Syntax = Syntax {
+    line_comment: Some("#"),
+    comment_starts_word: true,
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        Quote { open: "\"", close: "\"", escapes: true, multiline: true },
+        Quote { open: "'", close: "'", escapes: false, multiline: true }
+    ],
+    rust_literals: false,
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case",
+        "esac", "in", "function", "return", "local", "export", "readonly", "shift", "exit"
+    ],
+    key_separator: None,
+    tables: false,
+    variables: true
+};
+
+#[cfg(feature = "syntax")]
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(feature = "syntax")]
+fn next_char_len(line: &str, at: usize) -> usize {
+    line[at..].chars().next().map_or(1, |c| c.len_utf8())
+}
+
+/// Returns the end of a string whose contents start at `at`, just after
+/// its closing delimiter, or `None` if the line ends first.
+#[cfg(feature = "syntax")]
+fn string_end(line: &str, mut at: usize, close: &str, escapes: bool) -> Option<usize> {
+    while at < line.len() {
+        if escapes && line[at..].starts_with('\\') {
+            at += 1;
+            if at < line.len() {
+                at += next_char_len(line, at);
+            }
+        } else if line[at..].starts_with(close) {
+            return Some(at + close.len());
+        } else {
+            at += next_char_len(line, at);
+        }
+    }
+
+    None
+}
+
+#[cfg(feature = "syntax")]
+impl Syntax {
+
+    /// Continues a block comment from `at`, returns where it ends on this
+    /// line and how deep it is still nested there.
+    fn comment_end(&self, line: &str, mut at: usize, mut depth: usize) -> (usize, usize) {
+        let (open, close) = self.block_comment.unwrap();
+
+        while at < line.len() && depth > 0 {
+            if line[at..].starts_with(close) {
+                depth -= 1;
+                at += close.len();
+            } else if self.nested_comments && line[at..].starts_with(open) {
+                depth += 1;
+                at += open.len();
+            } else {
+                at += next_char_len(line, at);
+            }
+        }
+
+        (at, depth)
+    }
+
+    /// Continues the construct `state` describes from the start of `line`,
+    /// returns where it ends.
+    fn resume(&self, line: &str, state: &mut State, tokens: &mut Vec<Token>) -> usize {
+        let (end, style) = match state.clone() {
+            State::Normal => return 0,
+            State::Comment(depth) => {
+                let (end, depth) = self.comment_end(line, 0, depth);
+                *state = if depth == 0 { State::Normal } else { State::Comment(depth) };
+                (end, Style::Comment)
+            },
+            State::Str(close, escapes) => match string_end(line, 0, &close, escapes) {
+                Some(end) => {
+                    *state = State::Normal;
+                    (end, Style::String)
+                },
+                None => (line.len(), Style::String)
+            }
+        };
+
+        if end > 0 {
+            tokens.push(Token::new(0..end, style));
+        }
+
+        end
+    }
+
+    /// Highlights a `key =` or `[table]` at the start of the line, returns
+    /// where highlighting should go on.
+    fn key(&self, line: &str, tokens: &mut Vec<Token>) -> usize {
+        let start = line.len() - line.trim_start().len();
+        let mut rest = &line[start..];
+        let mut key_start = start;
+
+        if self.tables && rest.starts_with('[') {
+            let end = rest.find(']').map_or(line.len(), |n| start + n + 1);
+            tokens.push(Token::new(start..end, Style::Key));
+            return end;
+        }
+
+        let separator = match self.key_separator {
+            Some(c) => c,
+            None => return 0
+        };
+
+        // YAML list items can start with a key as well
+        if separator == ':' && rest.starts_with("- ") {
+            rest = &rest[2..];
+            key_start += 2;
+        }
+
+        let key_len = rest.find(|c: char| !(is_word_char(c) || c == '-' || c == '.')).unwrap_or(rest.len());
+        if key_len == 0 {
+            return 0;
+        }
+
+        let after = rest[key_len..].trim_start();
+        let is_key = match separator {
+            ':' => after == ":" || after.starts_with(": ") || after.starts_with(":\t"),
+            c => after.starts_with(c)
+        };
+
+        if is_key {
+            tokens.push(Token::new(key_start..key_start + key_len, Style::Key));
+            key_start + key_len
+        } else {
+            0
+        }
+    }
+
+    /// Recognizes a Rust raw string or char literal at `at`.
+    fn rust_literal(&self, line: &str, at: usize) -> Option<Literal> {
+        let rest = &line[at..];
+
+        let prefix = if rest.starts_with("br") { 2 } else if rest.starts_with('r') { 1 } else { 0 };
+        if prefix > 0 {
+            let after = &rest[prefix..];
+            let hashes = after.len() - after.trim_start_matches('#').len();
+            if after[hashes..].starts_with('"') {
+                return Some(Literal::RawString(format!("\"{}", "#".repeat(hashes)), at + prefix + hashes + 1));
+            }
+        }
+
+        if !rest.starts_with('\'') {
+            return None;
+        }
+
+        // anything else starting with a quote is a lifetime
+        let mut chars = rest[1..].chars();
+        let len = match chars.next() {
+            Some('\\') => {
+                let from = 2 + chars.next().map_or(0, |c| c.len_utf8());
+                rest[from..].find('\'').map(|n| from + n + 1)
+            },
+            Some(c) if c != '\'' && chars.next() == Some('\'') => Some(1 + c.len_utf8() + 1),
+            _ => None
+        };
+
+        len.map(|len| Literal::Char(at + len))
+    }
+
+}
+
+#[cfg(feature = "syntax")]
+impl Highlighter for Syntax {
+    fn highlight_line(&self, line: &str, state: &mut State) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut at = self.resume(line, state, &mut tokens);
+
+        if at == 0 {
+            at = self.key(line, &mut tokens);
+        }
+
+        while at < line.len() {
+            let rest = &line[at..];
+            let previous = line[..at].chars().next_back();
+            let after_space = previous.is_none_or(char::is_whitespace);
+            let word_boundary = previous.is_none_or(|c| !is_word_char(c));
+
+            if let Some(comment) = self.line_comment {
+                if rest.starts_with(comment) && (!self.comment_starts_word || after_space) {
+                    tokens.push(Token::new(at..line.len(), Style::Comment));
+                    break;
+                }
+            }
+
+            if let Some((open, _)) = self.block_comment {
+                if rest.starts_with(open) {
+                    let (end, depth) = self.comment_end(line, at + open.len(), 1);
+                    tokens.push(Token::new(at..end, Style::Comment));
+                    if depth > 0 {
+                        *state = State::Comment(depth);
+                    }
+                    at = end;
+                    continue;
+                }
+            }
+
+            if self.rust_literals && word_boundary {
+                match self.rust_literal(line, at) {
+                    Some(Literal::Char(end)) => {
+                        tokens.push(Token::new(at..end, Style::String));
+                        at = end;
+                        continue;
+                    },
+                    Some(Literal::RawString(close, contents)) => {
+                        let end = match string_end(line, contents, &close, false) {
+                            Some(end) => end,
+                            None => {
+                                *state = State::Str(close, false);
+                                line.len()
+                            }
+                        };
+                        tokens.push(Token::new(at..end, Style::String));
+                        at = end;
+                        continue;
+                    },
+                    None => ()
+                }
+            }
+
+            if let Some(quote) = self.quotes.iter().find(|q| rest.starts_with(q.open)) {
+                let end = match string_end(line, at + quote.open.len(), quote.close, quote.escapes) {
+                    Some(end) => end,
+                    None => {
+                        if quote.multiline {
+                            *state = State::Str(quote.close.to_string(), quote.escapes);
+                        }
+                        line.len()
+                    }
+                };
+                tokens.push(Token::new(at..end, Style::String));
+                at = end;
+                continue;
+            }
+
+            if self.variables && rest.starts_with('$') {
+                let end = if rest.starts_with("${") {
+                    rest.find('}').map_or(line.len(), |n| at + n + 1)
+                } else {
+                    at + 1 + rest[1..].find(|c: char| !is_word_char(c)).unwrap_or(rest.len() - 1)
+                };
+
+                if end > at + 1 {
+                    tokens.push(Token::new(at..end, Style::Variable));
+                    at = end;
+                    continue;
+                }
+            }
+
+            let c = rest.chars().next().unwrap();
+
+            if word_boundary && is_word_char(c) {
+                let len = rest.find(|c: char| !is_word_char(c)).unwrap_or(rest.len());
+                let word = &rest[..len];
+
+                if c.is_ascii_digit() {
+                    tokens.push(Token::new(at..at + len, Style::Number));
+                } else if self.keywords.contains(&word) {
+                    tokens.push(Token::new(at..at + len, Style::Keyword));
+                }
+
+                at += len;
+                continue;
+            }
+
+            at += c.len_utf8();
+        }
+
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Marks whole lines as comments between lines `/*` and `*/`, counting
+    /// the lines it highlights.
+    struct Blocks(Cell<usize>);
+
+    impl Highlighter for Blocks {
+        fn highlight_line(&self, line: &str, state: &mut State) -> Vec<Token> {
+            self.0.set(self.0.get() + 1);
+
+            let inside = *state != State::Normal || line == "/*";
+            *state = match line {
+                "/*" => State::Comment(1),
+                "*/" => State::Normal,
+                _ => state.clone()
+            };
+
+            if inside { vec![Token::new(0..line.len(), Style::Comment)] } else { Vec::new() }
+        }
+    }
+
+    #[test]
+    fn caches_states() {
+        let mut lines: Vec<String> = (0..1000).map(|n| n.to_string()).collect();
+        lines[300] = "/*".to_string();
+        lines[700] = "*/".to_string();
+        let buffer: Buffer = lines.into_iter().collect();
+
+        let highlighter = Blocks(Cell::new(0));
+        let mut states = StateCache::new();
+
+        assert_eq!(states.state_at(&highlighter, &buffer, 600), State::Comment(1));
+        assert_eq!(highlighter.0.get(), 600);
+
+        // from the state recorded at line 512
+        assert_eq!(states.state_at(&highlighter, &buffer, 800), State::Normal);
+        assert_eq!(highlighter.0.get(), 600 + 800 - 512);
+
+        highlighter.0.set(0);
+        assert_eq!(states.state_at(&highlighter, &buffer, 520), State::Comment(1));
+        assert_eq!(highlighter.0.get(), 8);
+
+        states.invalidate_from(100);
+        assert_eq!(states.state_at(&highlighter, &buffer, 300), State::Normal);
+        assert_eq!(highlighter.0.get(), 8 + 300);
+    }
+}
@@ -0,0 +1,150 @@
+use buffer::Buffer;
+use cmd::{
+    Cmd,
+    Command
+};
+use pos::{
+    Pos,
+    Range
+};
+
+/// Number of commands kept in the history.
+const MAX_HISTORY: usize = 1000;
+
+/// Commands executed during the session, oldest first.
+///
+/// Besides the list, the last command that changed a buffer is kept, along
+/// with the number of lines it applied to and the text it inserted, so
+/// that it can be repeated at another line.
+#[derive(Debug)]
+pub struct History {
+    commands: Vec<Command>,
+    last_change: Option<Change>
+}
+
+/// A command that changed a buffer.
+#[derive(Debug, Clone)]
+pub struct Change {
+    command: Command,
+    lines: usize,
+    text: Option<Buffer>
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+
+    pub fn new() -> History {
+        History {
+            commands: Vec::new(),
+            last_change: None
+        }
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// Returns the `n`th command, counting from 1.
+    pub fn get(&self, n: usize) -> Option<&Command> {
+        if n == 0 {
+            None
+        } else {
+            self.commands.get(n - 1)
+        }
+    }
+
+    /// Adds `command`, which applied to `lines` lines, to the history. It
+    /// becomes the last change if it modifies a buffer.
+    pub fn add(&mut self, command: Command, lines: usize) {
+        if is_change(&command.cmd) {
+            self.last_change = Some(Change {
+                command: command.clone(),
+                lines,
+                text: None
+            });
+        }
+
+        self.commands.push(command);
+        if self.commands.len() > MAX_HISTORY {
+            self.commands.remove(0);
+        }
+    }
+
+    /// Records the text typed for the last change, once its input ended.
+    pub fn set_change_text(&mut self, text: Buffer) {
+        if let Some(ref mut change) = self.last_change {
+            change.text = Some(text);
+        }
+    }
+
+    pub fn last_change(&self) -> Option<&Change> {
+        self.last_change.as_ref()
+    }
+
+}
+
+impl Change {
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Number of lines the change applied to, at least 1.
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// The lines inserted by an insert command.
+    pub fn text(&self) -> Option<&Buffer> {
+        self.text.as_ref()
+    }
+
+    /// Returns the command of the change applied to `range` instead.
+    pub fn at(&self, range: Range) -> Command {
+        let cmd = match self.command.cmd {
+            Cmd::Delete(_, register) => Cmd::Delete(range, register),
+            Cmd::Transfer(_, ref target) => Cmd::Transfer(range, target.clone()),
+            Cmd::Move(_, ref target) => Cmd::Move(range, target.clone()),
+            Cmd::Put(_, register) => Cmd::Put(range, register),
+            Cmd::EnterInsertMode(_) => Cmd::EnterInsertMode(range),
+            Cmd::Substitute(_, ref substitution) => Cmd::Substitute(range, substitution.clone()),
+            ref cmd => cmd.clone()
+        };
+
+        Command {
+            cmd,
+            suffix: self.command.suffix.clone()
+        }
+    }
+
+    /// Returns the range starting at `line` and spanning as many lines as
+    /// the change did.
+    pub fn range_at(&self, line: usize) -> Range {
+        if self.lines > 1 {
+            Range::Range(Pos::Line(line), Pos::Line(line + self.lines - 1))
+        } else {
+            Range::Line(Pos::Line(line))
+        }
+    }
+
+}
+
+/// Whether `cmd` modifies the buffer it applies to.
+pub fn is_change(cmd: &Cmd) -> bool {
+    matches!(*cmd, Cmd::Delete(_, _) | Cmd::Transfer(_, _) | Cmd::Move(_, _) |
+                   Cmd::Put(_, _) | Cmd::EnterInsertMode(_) | Cmd::Substitute(_, _))
+}
+
+/// The range a change command applies to.
+pub fn command_range(cmd: &Cmd) -> Option<&Range> {
+    match *cmd {
+        Cmd::Delete(ref r, _) | Cmd::Transfer(ref r, _) | Cmd::Move(ref r, _) |
+        Cmd::Put(ref r, _) | Cmd::EnterInsertMode(ref r) | Cmd::Substitute(ref r, _) => Some(r),
+        _ => None
+    }
+}
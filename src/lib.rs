@@ -1,18 +1,65 @@
 extern crate regex;
+extern crate memmap2;
+extern crate encoding_rs;
+extern crate libc;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "xz")]
+extern crate xz2;
 
 pub mod buffer;
 pub mod ui;
 pub mod pos;
 pub mod cmd;
 pub mod util;
+pub mod document;
+pub mod register;
+pub mod codec;
+pub mod charset;
+pub mod search;
+pub mod dialect;
+pub mod term;
+pub mod highlight;
+pub mod editor;
+pub mod history;
+pub mod macros;
+pub mod config;
+pub mod swap;
+pub mod stamp;
+pub mod diff;
+
+mod rope;
+mod mapped;
+mod visual;
+mod signal;
+#[cfg(test)]
+mod testutil;
 
 use std::result;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::collections::{
+    BTreeMap,
+    HashSet
+};
+use std::env;
+use std::fs;
 use std::fs::File;
-use std::path::Path;
 use std::io;
+use std::io::{
+    BufRead,
+    Write
+};
 use std::convert;
-
-use std::ffi::OsString;
+use std::fmt;
+use std::cmp;
+use std::ops;
+use std::mem;
+use std::rc::Rc;
 
 use self::cmd::Cmd;
 
@@ -24,16 +71,36 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum ErrorType {
     Unknown,
     ParseError,
+    SyntaxError(usize),
     IoError(io::Error),
-    UnimplementedCmd(Cmd),
-    UnimplementedAction(ui::Action),
-    InvalidRange(pos::Range),
+    UnimplementedCmd(Box<Cmd>),
+    UnimplementedAction(Box<ui::Action>),
+    InvalidRange(Box<pos::Range>),
+    UnknownMark(char),
+    UnknownBuffer(usize),
+    EmptyRegister(Option<char>),
+    UnsupportedCodec(codec::Codec),
+    UnknownEncoding,
+    InvalidEncoding(charset::Charset),
+    NoMatch,
+    NoPreviousPattern,
+    UnknownHistoryEntry(usize),
+    NoPreviousChange,
+    NoPreviousReplacement,
+    UnknownMacro(char),
+    UnknownOption(String),
+    UnknownAlias(String),
+    Interrupted,
+    ChangedOnDisk,
+    /// The command goes on in the next input line.
+    Unfinished,
 }
 
 #[derive(Debug)]
 pub struct Error {
     msg: String,
-    error: ErrorType
+    error: ErrorType,
+    output: ui::Output
 }
 
 impl convert::From<regex::Error> for Error {
@@ -52,21 +119,84 @@ impl Error {
     pub fn unknown(msg: &str) -> Error {
         Error {
             msg: msg.to_string(),
-            error: ErrorType::Unknown
+            error: ErrorType::Unknown,
+            output: ui::Output::new()
         }
     }
 
     pub fn new(error: ErrorType) -> Error {
-        Error { 
-            msg: String::new(), 
-            error: error 
+        Error {
+            msg: String::new(),
+            error,
+            output: ui::Output::new()
         }
     }
 
     pub fn detailed<T: ToString>(error: ErrorType, msg: T) -> Error {
         Error {
             msg: msg.to_string(),
-            error: error
+            error,
+            output: ui::Output::new()
+        }
+    }
+
+    pub fn error_type(&self) -> &ErrorType {
+        &self.error
+    }
+
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+
+    /// What the failed command printed before the error.
+    pub fn output(&self) -> &ui::Output {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> ui::Output {
+        mem::take(&mut self.output)
+    }
+}
+
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorType::Unknown => write!(f, "unknown error"),
+            ErrorType::ParseError => write!(f, "parse error"),
+            ErrorType::SyntaxError(pos) => write!(f, "syntax error at column {}", pos + 1),
+            ErrorType::IoError(ref e) => write!(f, "{}", e),
+            ErrorType::UnimplementedCmd(ref cmd) => write!(f, "'{}' isn't implemented", cmd::Command::new((**cmd).clone())),
+            ErrorType::UnimplementedAction(ref action) => write!(f, "'{}' isn't implemented", action),
+            ErrorType::InvalidRange(ref r) => write!(f, "invalid address '{}'", r),
+            ErrorType::UnknownMark(c) => write!(f, "mark '{}' isn't set", c),
+            ErrorType::UnknownBuffer(n) => write!(f, "no buffer {}", n),
+            ErrorType::EmptyRegister(Some(c)) => write!(f, "register '{}' is empty", c),
+            ErrorType::EmptyRegister(None) => write!(f, "the unnamed register is empty"),
+            ErrorType::UnsupportedCodec(codec) => write!(f, "rsed was built without {} support", codec),
+            ErrorType::UnknownEncoding => write!(f, "unknown encoding"),
+            ErrorType::InvalidEncoding(charset) => write!(f, "invalid {} text", charset),
+            ErrorType::NoMatch => write!(f, "no match"),
+            ErrorType::NoPreviousPattern => write!(f, "no previous pattern"),
+            ErrorType::UnknownHistoryEntry(n) => write!(f, "no command {} in the history", n),
+            ErrorType::NoPreviousChange => write!(f, "no previous change"),
+            ErrorType::NoPreviousReplacement => write!(f, "no previous replacement"),
+            ErrorType::UnknownMacro(c) => write!(f, "macro '{}' is empty", c),
+            ErrorType::UnknownOption(ref name) => write!(f, "unknown option '{}'", name),
+            ErrorType::UnknownAlias(ref name) => write!(f, "unknown command ':{}'", name),
+            ErrorType::Interrupted => write!(f, "interrupted"),
+            ErrorType::ChangedOnDisk => write!(f, "the file changed on disk since it was read"),
+            ErrorType::Unfinished => write!(f, "unfinished command")
+        }
+    }
+}
+
+/// The detailed message if there is one, else the message of the error type.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.msg.is_empty() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{}", self.msg)
         }
     }
 }
@@ -86,114 +216,682 @@ impl InputInformation {
     }
 }
 
+/// Brings the swap files of `documents` up to date, see
+/// `Document::update_swap`.
+fn update_swaps(documents: &mut [document::Document], flush: bool) {
+    // a swap file that can't be written shouldn't stop the editing
+    for document in documents {
+        let _ = document.update_swap(flush);
+    }
+}
+
+/// The highlighter set with `Rsed::set_highlighter`, or else the one for the
+/// file name of `document`.
+fn highlighter_for<'a>(highlighter: &'a Option<Rc<dyn highlight::Highlighter>>,
+                       document: &'a document::Document) -> Option<&'a dyn highlight::Highlighter> {
+    match *highlighter {
+        Some(ref highlighter) => Some(&**highlighter),
+        None => document.file_name().and_then(highlight::for_path)
+    }
+}
+
 #[derive(Debug)]
 pub struct Rsed {
-    current_buffer: buffer::Buffer,
+    documents: Vec<document::Document>,
+    current_document: usize,
     input_info: Option<InputInformation>,
-    current_line: usize,
+    registers: register::Registers,
+    search: search::Search,
+    /// The replacement of the last `s`, which `%` stands for.
+    last_replacement: Option<String>,
+    history: history::History,
+    /// Whether the command list of a `g` or `v` is running.
+    in_global: bool,
+    macros: macros::Macros,
+    aliases: BTreeMap<String, String>,
+    expanding: Vec<String>,
+    backup: config::Backup,
+    backed_up: HashSet<PathBuf>,
+    diff_context: usize,
     ui: ui::Ui,
     running: bool,
-    file_name: Option<OsString>,
+    map_files: bool,
+    /// Whether the output goes to a terminal, which `:set color=on` needs.
+    terminal: bool,
+    window: usize,
+    codec: Option<codec::Codec>,
+    encoding: Option<&'static encoding_rs::Encoding>,
+    highlighter: Option<Rc<dyn highlight::Highlighter>>
+}
+
+impl Default for Rsed {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Rsed {
 
     pub fn new() -> Rsed {
         Rsed {
-            current_buffer: buffer::Buffer::new(),
+            documents: vec![document::Document::new()],
+            current_document: 0,
             input_info: None,
-            current_line: 1,
+            registers: register::Registers::new(),
+            search: search::Search::new(),
+            last_replacement: None,
+            history: history::History::new(),
+            in_global: false,
+            macros: macros::Macros::new(),
+            aliases: BTreeMap::new(),
+            expanding: Vec::new(),
+            backup: config::Backup::Off,
+            backed_up: HashSet::new(),
+            diff_context: diff::DEFAULT_CONTEXT,
             ui: ui::Ui::new(),
             running: true,
-            file_name: None
+            map_files: false,
+            terminal: false,
+            window: term::window_size(),
+            codec: None,
+            encoding: None,
+            highlighter: None
         }
     }
 
+    /// Editor with the file at `path` opened, like the `e` command opens it.
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Rsed> {
-        let file_name = path.as_ref().as_os_str().to_os_string();
-        let file = try!(File::open(path));
-        let reader = io::BufReader::new(file);
-        let buffer = try!(buffer::Buffer::from_buf_read(reader));
+        let mut rsed = Rsed::new();
+        rsed.documents[0] = rsed.open_document(path)?;
+        Ok(rsed)
+    }
 
-        Ok(Rsed {
-            current_buffer: buffer,
-            input_info: None,
-            current_line: 1,
-            ui: ui::Ui::new(),
-            running: true,
-            file_name: Some(file_name)
-        })
+    /// Selects whether files are memory mapped instead of read into memory
+    /// when they are opened.
+    pub fn set_map_files(&mut self, map_files: bool) {
+        self.map_files = map_files;
     }
 
-    pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let file_name = Some(path.as_ref().as_os_str().to_os_string());
+    /// Forces files to be written with `codec`. With `None`, every file is
+    /// written compressed the way it was when it was read.
+    pub fn set_codec(&mut self, codec: Option<codec::Codec>) {
+        self.codec = codec;
+    }
+
+    /// Sets the encoding of files that don't start with a byte order mark.
+    /// With `None` they are read as UTF-8.
+    pub fn set_encoding(&mut self, encoding: Option<&'static encoding_rs::Encoding>) {
+        self.encoding = encoding;
+    }
+
+    /// Enables or disables ANSI colours in the output, wherever it goes.
+    pub fn set_color(&mut self, color: bool) {
+        self.ui.set_color(color);
+    }
+
+    /// Tells whether the output goes to a terminal. Colours can only be
+    /// turned on with `:set color=on` if it does, so that a configuration
+    /// file doesn't put escape codes into pipes.
+    pub fn set_terminal(&mut self, terminal: bool) {
+        self.terminal = terminal;
+    }
+
+    /// Highlights the syntax of every document with `highlighter` when
+    /// colours are enabled. With `None` a built-in highlighter is picked by
+    /// the file name of each document.
+    pub fn set_highlighter(&mut self, highlighter: Option<Rc<dyn highlight::Highlighter>>) {
+        self.highlighter = highlighter;
+        for document in &self.documents {
+            document.highlight_states().borrow_mut().clear();
+        }
+    }
+
+    /// Sets the POSIX dialect patterns are written in.
+    pub fn set_dialect(&mut self, dialect: dialect::Dialect) {
+        self.search.set_dialect(dialect);
+    }
+
+    fn open_document<P: AsRef<Path>>(&self, path: P) -> Result<document::Document> {
+        let mut document = match self.encoding {
+            None if self.map_files => document::Document::open_mapped(&path),
+            encoding => document::Document::open(&path, encoding)
+        }?;
 
-        let file = try!(File::open(path));
-        let reader = io::BufReader::new(file);
-        
-        self.current_buffer = try!(buffer::Buffer::from_buf_read(reader));
-        self.file_name = file_name;
+        self.attach_swap(&mut document, path.as_ref())?;
+        Ok(document)
+    }
+
+    /// Gives `document`, read from `path`, a swap file.
+    ///
+    /// A swap file left behind by an earlier session is offered for
+    /// recovery when input comes from a terminal. Otherwise it is kept as
+    /// it is and the document isn't journaled. Neither is a document still
+    /// reading from a mapped file, which would be copied in full each time.
+    fn attach_swap(&self, document: &mut document::Document, path: &Path) -> Result<()> {
+        let swap = swap::Swap::new(path);
+
+        if swap.path().exists() {
+            let mut stderr = io::stderr();
+
+            if !term::is_stdin_tty() {
+                writeln!(stderr, "rsed: found swap file {}, not journaling {}", swap.path().display(), path.display())?;
+                return Ok(());
+            }
+
+            write!(stderr, "found swap file {}, recover it? (y/n) ", swap.path().display())?;
+            stderr.flush()?;
+
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+
+            match answer.trim() {
+                "y" | "yes" => document.replace_buffer(swap::recover(swap.path())?),
+                "n" | "no" => fs::remove_file(swap.path())?,
+                _ => return Ok(())
+            }
+        }
+
+        if !document.buffer().is_mapped() {
+            document.set_swap(Some(swap));
+        }
+        Ok(())
+    }
+
+    pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        // the buffer is replaced, and a swap of the same file would be
+        // offered for recovery
+        self.doc_mut().remove_swap();
+        let document = self.open_document(path)?;
+        self.documents[self.current_document] = document;
 
         Ok(())
     }
 
-    pub fn main_loop(&mut self) {
+    /// Runs rsed on standard input and output. Input is read with the line
+    /// editor if both are a terminal, otherwise as plain lines.
+    pub fn main_loop(&mut self) -> Result<()> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
 
-        let mut stdin = io::stdin();
+        signal::install();
+
+        if term::is_interactive() {
+            let mut editor = editor::LineEditor::new(editor::LineEditor::default_history_file());
+            // the line editor shows the prompt itself
+            return self.run_with(|rsed| rsed.read_interactive(&mut editor), &mut stdout.lock(), false);
+        }
+
+        let mut input = io::BufReader::new(signal::Interruptible::new(stdin.lock()));
+        self.run(&mut input, &mut stdout.lock())
+    }
+
+    /// Reads input lines from `reader` until `q` or end of input, writing
+    /// everything rsed prints to `writer`.
+    ///
+    /// Errors of individual commands are reported on `writer`, failing to
+    /// write to it ends the loop with an error.
+    pub fn run<R: BufRead, W: Write>(&mut self, reader: &mut R, writer: &mut W) -> Result<()> {
+        self.run_with(|rsed| rsed.ui.get_input(reader), writer, true)
+    }
+
+    fn run_with<F, W>(&mut self, mut read: F, writer: &mut W, show_prompt: bool) -> Result<()>
+        where F: FnMut(&mut Rsed) -> Result<Option<String>>, W: Write {
 
         while self.running {
-            let parsed_action = self.ui.get_input(&mut stdin);
+            if show_prompt && !self.ui.is_insert_mode() && !self.ui.is_continued() && !self.ui.prompt().is_empty() {
+                write!(writer, "{}", self.ui.prompt())?;
+                writer.flush()?;
+            }
 
-            let action_result = match parsed_action {
-                Ok(action) => self.handle_action(action),
+            let result = match read(self) {
+                Ok(Some(input)) => self.execute(&input),
+                Ok(None) => break,
                 Err(e) => Err(e)
             };
 
-            if let Err(e) = action_result {
-                println!("? {:?}", e);
+            if signal::hangup_received() {
+                return self.save_on_hangup();
+            }
+
+            // an interrupt abandons the command, or ends input mode keeping
+            // the lines entered so far, and is reported as a bare `?`
+            let interrupted = signal::take_interrupt();
+            if interrupted && self.ui.is_insert_mode() {
+                let _ = self.end_insert_mode();
+            }
+            if interrupted {
+                self.ui.cancel_continuation();
+            }
+
+            // lines printed before an error or interrupt are still shown
+            match result {
+                Ok(output) => {
+                    self.ui.write_output(writer, output)?;
+                    if interrupted {
+                        self.ui.write_error(writer, &Error::new(ErrorType::Interrupted))?;
+                    }
+                },
+                Err(mut e) => {
+                    self.ui.write_output(writer, e.take_output())?;
+                    if interrupted {
+                        self.ui.write_error(writer, &Error::new(ErrorType::Interrupted))?;
+                    } else {
+                        self.ui.write_error(writer, &e)?;
+                    }
+                }
+            }
+
+            update_swaps(&mut self.documents, false);
+        }
+
+        // quitting discards what wasn't written
+        for document in &mut self.documents {
+            document.remove_swap();
+        }
+
+        Ok(())
+    }
+
+    /// Writes the current buffer to `ed.hup` in the current directory, or
+    /// else in the home directory, if it has unsaved changes, like ed does
+    /// when the terminal hangs up. The swap files of all buffers are
+    /// brought up to date and kept.
+    fn save_on_hangup(&mut self) -> Result<()> {
+        self.running = false;
+        update_swaps(&mut self.documents, true);
+
+        if !self.doc().has_changes() {
+            return Ok(());
+        }
+
+        let home = env::var_os("HOME").map(|home| PathBuf::from(home).join("ed.hup"));
+        let mut result = Ok(());
+
+        for path in Some(PathBuf::from("ed.hup")).into_iter().chain(home) {
+            result = File::create(&path)
+                .map_err(Error::from)
+                .and_then(|file| {
+                    let mut writer = io::BufWriter::new(file);
+                    self.doc().buffer().write(&mut writer)?;
+                    writer.flush()?;
+                    Ok(())
+                });
+            if result.is_ok() {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Feeds a single input line to rsed, as if it was typed at the prompt,
+    /// and returns everything printed while handling it.
+    ///
+    /// Depending on the current mode the line is either parsed as a command
+    /// or appended to the text being inserted. A command continued with a
+    /// backslash runs once its last line is fed. If the command fails, what
+    /// it printed before is in the error's `output`.
+    pub fn execute(&mut self, input: &str) -> Result<ui::Output> {
+        let result = self.ui.parse_input(input).and_then(|action| match action {
+            Some(action) => {
+                self.handle_action(action.clone())?;
+                self.macros.record(&action);
+                Ok(())
+            },
+            None => Ok(())
+        });
+        let output = self.ui.take_output();
+
+        match result {
+            Ok(()) => Ok(output),
+            Err(mut e) => {
+                e.output = output;
+                Err(e)
             }
         }
     }
 
+    /// Reads a line with the line editor. Commands go into the history and
+    /// complete file and mark names, text typed in insert mode and the lines
+    /// continuing a command don't.
+    fn read_interactive(&mut self, editor: &mut editor::LineEditor) -> Result<Option<String>> {
+        let completer = editor::CommandCompleter::new(self.doc().mark_names());
+
+        // edits left for the next swap update are journaled once the user
+        // pauses
+        let documents = &mut self.documents;
+        let mut idle = || update_swaps(documents, true);
+
+        if self.ui.is_insert_mode() || self.ui.is_continued() {
+            return editor.read_line("", None, &mut idle);
+        }
+
+        let line = editor.read_line(self.ui.prompt(), Some(&completer), &mut idle)?;
+
+        if let Some(ref line) = line {
+            editor.add_history(line.trim_end_matches('\n'));
+        }
+
+        Ok(line)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
     fn handle_action(&mut self, action: ui::Action) -> Result<()> {
         use ui::Action::*;
 
         match action {
-            Command(Cmd::Quit) => Ok(self.running = false),
-            Command(Cmd::Delete(r)) => self.delete(r),
-            Command(Cmd::Print(r, option)) => self.print_range(r, option),
-            Command(Cmd::Jump(r)) => self.jump_to(r),
-            Command(Cmd::PrintLineNumber(r)) => self.print_line_number(r),
-            Command(Cmd::JumpNext) => self.jump_next(),
-            Command(Cmd::Edit(f)) => self.read_file(f),
-            Command(Cmd::Write(Some(f))) => self.write_file(f),
-            Command(Cmd::Write(None)) => self.write_same_file(),
-            Command(Cmd::EnterInsertMode(r)) => self.enter_insert_mode(r),
-            Command(rest) => Err(Error::new(ErrorType::UnimplementedCmd(rest))),
-
+            Command(command) => self.handle_command(command),
             Insert(s) => self.insert_line(s),
             InsertEnd => self.end_insert_mode()
         }
     }
 
-    fn write_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let file_name = Some(path.as_ref().as_os_str().to_os_string());
+    fn handle_command(&mut self, command: cmd::Command) -> Result<()> {
+        // a mapped file changed by someone else can only be read again or
+        // let go of
+        let reads_buffer = !matches!(command.cmd,
+            Cmd::Quit | Cmd::Reload(_) | Cmd::Edit(_) | Cmd::OpenBuffer(_) | Cmd::ListBuffers |
+            Cmd::SwitchBuffer(_) | Cmd::CloseBuffer(_) | Cmd::Set(_, _) | Cmd::ListAliases |
+            Cmd::DefineAlias(_, _) | Cmd::ListHistory | Cmd::Record(_) | Cmd::ListMacros |
+            Cmd::SaveMacros(_) | Cmd::LoadMacros(_));
+        if reads_buffer {
+            self.doc().check_mapping()?;
+        }
+
+        // history commands aren't recorded, the commands they run are, except
+        // those of a `g` command list
+        let record = match command.cmd {
+            _ if self.in_global => None,
+            Cmd::ListHistory | Cmd::Rerun(_) | Cmd::RepeatChange(_) |
+            Cmd::RunMacro(_, _, _) | Cmd::RunAlias(_) => None,
+            ref cmd => {
+                let lines = match history::command_range(cmd).map(|r| r.to_range(self)) {
+                    Some(Ok(range)) => cmp::max(range.len(), 1),
+                    _ => 1
+                };
+                Some((command.clone(), lines))
+            }
+        };
+
+        match command.cmd {
+            Cmd::Quit => {
+                self.running = false;
+                Ok(())
+            },
+            Cmd::Mark(r, c) => self.set_mark(r, c),
+            Cmd::Transfer(r, target) => self.transfer(r, target),
+            Cmd::Move(r, target) => self.move_lines(r, target),
+            Cmd::OpenBuffer(f) => self.open_buffer(f),
+            Cmd::ListBuffers => self.list_buffers(),
+            Cmd::SwitchBuffer(n) => self.switch_buffer(n),
+            Cmd::CloseBuffer(n) => self.close_buffer(n),
+            Cmd::Encoding(label, bom) => self.encoding(label, bom),
+            Cmd::ListMatches(r, pattern, context) => self.list_matches(r, pattern, context),
+            Cmd::Scroll(r, lines, option) => self.scroll(r, lines, option),
+            Cmd::Visual => self.visual(),
+            Cmd::ListHistory => self.list_history(),
+            Cmd::Rerun(n) => self.rerun(n),
+            Cmd::RepeatChange(r) => self.repeat_change(r),
+            Cmd::Record(Some(c)) => self.macros.start_recording(c),
+            Cmd::Record(None) => self.macros.stop_recording(),
+            Cmd::RunMacro(r, c, count) => self.run_macro(r, c, count),
+            Cmd::ListMacros => self.list_macros(),
+            Cmd::SaveMacros(f) => self.macros.save(f),
+            Cmd::LoadMacros(f) => self.macros.load(f),
+            Cmd::Set(None, _) => self.list_options(),
+            Cmd::Set(Some(name), None) => self.show_option(&name),
+            Cmd::Set(Some(name), Some(value)) => self.set_option(&name, &value),
+            Cmd::ListAliases => self.list_aliases(),
+            Cmd::DefineAlias(name, body) => {
+                self.define_alias(name, body);
+                Ok(())
+            },
+            Cmd::RunAlias(name) => self.run_alias(&name),
+            Cmd::Delete(r, register) => self.delete(r, register),
+            Cmd::Yank(r, register) => self.yank(r, register),
+            Cmd::Put(r, register) => self.put(r, register),
+            Cmd::Print(r, option) => self.print_range(r, option),
+            Cmd::Jump(r) => self.jump_to(r),
+            Cmd::PrintLineNumber(r) => self.print_line_number(r),
+            Cmd::JumpNext => self.jump_next(),
+            Cmd::Edit(f) => self.read_file(f),
+            Cmd::Write(Some(f), force) => self.write_file(f, force),
+            Cmd::Write(None, force) => self.write_same_file(force),
+            Cmd::Reload(force) => self.reload(force),
+            Cmd::Diff(target, context) => self.diff(target, context),
+            Cmd::EnterInsertMode(r) => self.enter_insert_mode(r),
+            Cmd::Substitute(r, substitution) => self.substitute(r, substitution),
+            Cmd::Global(r, pattern, invert, commands) => self.global(r, pattern, invert, commands),
+            rest => Err(Error::new(ErrorType::UnimplementedCmd(Box::new(rest))))
+        }?;
+
+        // lines that turned out not to be UTF-8 were cut short
+        if reads_buffer {
+            self.doc().check_mapping()?;
+        }
+
+        if let Some((command, lines)) = record {
+            self.history.add(command, lines);
+        }
+
+        match command.suffix {
+            Some(option) => self.print_range( pos::Range::current_line(), option ),
+            None => Ok(())
+        }
+    }
+
+    fn doc(&self) -> &document::Document {
+        &self.documents[self.current_document]
+    }
+
+    fn doc_mut(&mut self) -> &mut document::Document {
+        &mut self.documents[self.current_document]
+    }
+
+    fn resolve_pos(&self, document: &document::Document, pos: &pos::Pos) -> Result<usize> {
+        match *pos {
+            pos::Pos::Search(ref pattern, direction) =>
+                self.search.find_line(document.buffer(), document.current_line(), pattern, direction),
+            _ => document.convert(pos)
+        }
+    }
+
+    fn buffer_index(&self, n: usize) -> Result<usize> {
+        if n == 0 || n > self.documents.len() {
+            return Err(Error::new(ErrorType::UnknownBuffer(n)));
+        }
+
+        Ok(n - 1)
+    }
+
+    fn open_buffer<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let document = self.open_document(path)?;
+
+        self.documents.push(document);
+        self.current_document = self.documents.len() - 1;
+
+        Ok(())
+    }
+
+    fn list_buffers(&mut self) -> Result<()> {
+        for (index, document) in self.documents.iter().enumerate() {
+            let current = if index == self.current_document { '*' } else { ' ' };
+            let modified = if document.has_changes() { '+' } else { ' ' };
+            let name = match document.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => "[no name]".to_string()
+            };
+
+            self.ui.print(format!("{}{}{}\t{}", index + 1, current, modified, name));
+        }
+
+        Ok(())
+    }
+
+    fn switch_buffer(&mut self, n: usize) -> Result<()> {
+        self.current_document = self.buffer_index(n)?;
+        Ok(())
+    }
+
+    fn close_buffer(&mut self, n: Option<usize>) -> Result<()> {
+        let index = match n {
+            Some(n) => self.buffer_index(n)?,
+            None => self.current_document
+        };
+
+        if self.documents.len() == 1 {
+            return Err(Error::unknown("cannot close the only buffer"));
+        }
+
+        self.documents.remove(index).remove_swap();
+
+        if self.current_document > index || self.current_document == self.documents.len() {
+            self.current_document -= 1;
+        }
+
+        Ok(())
+    }
+
+    fn encoding(&mut self, label: Option<String>, bom: Option<bool>) -> Result<()> {
+        let label = match label {
+            Some(label) => label,
+            None => {
+                let charset = self.doc().charset();
+                self.ui.print(charset);
+                return Ok(());
+            }
+        };
+
+        let encoding = charset::encoding_for_label(&label)?;
+        let bom = bom.unwrap_or(encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE);
+
+        self.doc_mut().set_charset(charset::Charset::new(encoding, bom));
+        Ok(())
+    }
+
+    /// Writes the current buffer to `path`. Unless `force` is set, the
+    /// file the buffer was read from is only overwritten if nobody changed
+    /// it in the meantime.
+    fn write_file<P: AsRef<Path>>(&mut self, path: P, force: bool) -> Result<()> {
+        let file_name = path.as_ref().as_os_str().to_os_string();
+        let codec = self.codec.unwrap_or( self.doc().codec() );
+
+        let same_file = self.doc().file_name() == Some(&file_name);
+        if !force && same_file && self.doc().is_changed_on_disk()? {
+            return Err(Error::detailed(ErrorType::ChangedOnDisk,
+                                       "file changed on disk, 'w!' overwrites it, ':reload' reads it again"));
+        }
+
+        self.back_up(path.as_ref())?;
+
+        self.doc_mut().write(path, codec)?;
+
+        self.doc_mut().set_file_name(file_name);
+
+        Ok(())
+    }
+
+    /// Copies the file at `path` to `path~` before it is overwritten, as
+    /// the backup policy asks.
+    fn back_up(&mut self, path: &Path) -> Result<()> {
+        let wanted = match self.backup {
+            config::Backup::Off => false,
+            config::Backup::Once => !self.backed_up.contains(path),
+            config::Backup::Always => true
+        };
+
+        if !wanted || !path.is_file() {
+            return Ok(());
+        }
+
+        let mut backup = path.as_os_str().to_os_string();
+        backup.push("~");
+        fs::copy(path, &backup)?;
+
+        self.backed_up.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    fn write_same_file(&mut self, force: bool) -> Result<()> {
+        match self.doc().file_name().cloned() {
+            Some(file_name) => self.write_file(file_name, force),
+            None => Err(Error::unknown("no current filename"))
+        }
+    }
+
+    /// Reads the file of the current buffer again. Unsaved changes are only
+    /// discarded if `force` is set.
+    fn reload(&mut self, force: bool) -> Result<()> {
+        let file_name = match self.doc().file_name().cloned() {
+            Some(file_name) => file_name,
+            None => return Err(Error::unknown("no current filename"))
+        };
+
+        if !force && self.doc().has_changes() {
+            return Err(Error::unknown("buffer has unsaved changes, ':reload!' discards them"));
+        }
 
-        // TODO open will crash if file doesn't exist
-        let mut file = try!(File::open(path));
-        try!( self.current_buffer.write(&mut file) );
+        // the swap file of the changes being discarded isn't worth recovering
+        self.doc_mut().remove_swap();
 
-        self.file_name = file_name;
+        let line = self.doc().current_line();
+        let mut document = self.open_document(file_name)?;
+        document.set_current_line(cmp::min(line, document.len()));
 
+        self.documents[self.current_document] = document;
         Ok(())
     }
 
-    fn write_same_file(&self) -> Result<()> {
-        Err(Error::unknown("unimplemented"))
+    /// Prints a unified diff from `target` to the current buffer, by default
+    /// from the file the buffer was read from, with `context` lines around
+    /// the changes or as many as the `diffcontext` option says.
+    fn diff(&mut self, target: Option<cmd::DiffTarget>, context: Option<usize>) -> Result<()> {
+        let context = context.unwrap_or(self.diff_context);
+        let encoding = Some(self.doc().charset().encoding());
+
+        let opened;
+        let old = match target {
+            Some(cmd::DiffTarget::Buffer(n)) => &self.documents[self.buffer_index(n)?],
+            Some(cmd::DiffTarget::File(file)) => {
+                opened = document::Document::open(file, encoding)?;
+                &opened
+            },
+            None => match self.doc().file_name() {
+                Some(file_name) => {
+                    opened = document::Document::open(file_name, encoding)?;
+                    &opened
+                },
+                None => return Err(Error::unknown("no current filename"))
+            }
+        };
+        let new = self.doc();
+
+        let old_lines: Vec<&str> = old.lines(&(0..old.len())).collect();
+        let new_lines: Vec<&str> = new.lines(&(0..new.len())).collect();
+        let hunks = diff::unified(&old_lines, &new_lines, context)?;
+
+        if hunks.is_empty() {
+            return Ok(());
+        }
+
+        let name = |document: &document::Document| match document.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => "[no name]".to_string()
+        };
+        let header = vec![format!("--- {}", name(old)), format!("+++ {}", name(new))];
+
+        for line in header.into_iter().chain(hunks) {
+            self.ui.print_diff(line);
+        }
+
+        Ok(())
     }
 
     fn enter_insert_mode(&mut self, r: pos::Range) -> Result<()> {
-        if let Some(_) = self.input_info {
+        if self.input_info.is_some() {
             panic!()
         }
 
@@ -201,12 +899,14 @@ impl Rsed {
 
         self.input_info = Some(InputInformation::new(pos));
 
-        Ok(self.ui.set_mode( ui::Mode::Insert ))
+        self.ui.set_mode( ui::Mode::Insert );
+        Ok(())
     }
 
     fn insert_line(&mut self, s: String) -> Result<()> {
         if let Some(ref mut input_info) = self.input_info { 
-            Ok(input_info.input_buffer.add_line(s))
+            input_info.input_buffer.add_line(s);
+            Ok(())
         } else {
             panic!();
         }
@@ -215,69 +915,1103 @@ impl Rsed {
     fn end_insert_mode(&mut self) -> Result<()> {
 
         if let Some(input_info) = self.input_info.take() {
-            let pos = self.convert(&input_info.position);
-            let input_buffer = input_info.input_buffer;
+            let pos = self.convert(&input_info.position)?;
+            self.history.set_change_text(input_info.input_buffer.clone());
+            let count = self.doc_mut().insert_lines( pos, input_info.input_buffer );
 
-            self.current_line = pos + input_buffer.len();
-            self.current_buffer.insert_buffer( pos, input_buffer );
+            self.doc_mut().set_current_line(pos + count);
         } else {
             panic!();
         }
 
-        Ok(self.ui.set_mode( ui::Mode::Command ))
+        self.ui.set_mode( ui::Mode::Command );
+        Ok(())
     }
 
-    fn print_line_number(&self, r: pos::Range) -> Result<()> {
-        let range = r.to_range(self);
+    fn print_line_number(&mut self, r: pos::Range) -> Result<()> {
+        let range = r.to_range(self)?;
 
-        println!("{}", range.end);
+        self.ui.print(range.end);
         Ok(())
     }
 
-    fn delete(&mut self, r: pos::Range) -> Result<()> {
-        let range = r.to_range(self);
+    fn set_mark(&mut self, r: pos::Range, mark: char) -> Result<()> {
+        let line = self.convert(&pos::Pos::from(r))?;
 
-        self.current_buffer.delete_lines( range.start, range.end );
+        if line == 0 || self.doc().buffer().is_out_of_bounds(line) {
+            return Err(Error::unknown("invalid line"));
+        }
+
+        self.doc_mut().set_mark(mark, line);
         Ok(())
     }
 
-    fn print_range(&self, r: pos::Range, option: ui::PrintOption) -> Result<()> {
-       let range = r.to_range(self);
+    fn delete(&mut self, r: pos::Range, register: Option<char>) -> Result<()> {
+        self.yank(r.clone(), register)?;
 
-       if self.current_buffer.is_range_out_of_bounds(&range) {
-           return Err(Error::new(ErrorType::InvalidRange(r)));
-       }
+        let range = r.to_range(self)?;
+        self.doc_mut().delete_lines( &range );
+        Ok(())
+    }
 
-       let model = ui::DisplayModel::new( &self.current_buffer, range, option );
+    fn yank(&mut self, r: pos::Range, register: Option<char>) -> Result<()> {
+        let range = r.to_range(self)?;
 
-       self.ui.display( model );
+        if self.doc().buffer().is_range_out_of_bounds(&range) {
+            return Err(Error::new(ErrorType::InvalidRange(Box::new(r))));
+        }
 
-       Ok(())
+        let lines = self.doc().lines(&range).map(str::to_string).collect();
+        self.registers.set(register, lines);
+        Ok(())
     }
 
-    fn jump_to(&mut self, r: pos::Range) -> Result<()> {
-        self.current_line = self.convert( &pos::Pos::from(r) );
-        self.print_range( pos::Range::current_line(), ui::PrintOption::Normal)
-    }
+    fn put(&mut self, r: pos::Range, register: Option<char>) -> Result<()> {
+        let line = self.convert(&pos::Pos::from(r))?;
 
-    fn jump_next(&mut self) -> Result<()> {
-        if self.current_buffer.is_out_of_bounds(self.current_line + 1) {
+        if self.doc().buffer().is_out_of_bounds(line) {
             return Err(Error::unknown("invalid line"));
         }
 
-        self.current_line += 1;
-        self.print_range( pos::Range::current_line(), ui::PrintOption::Normal)
+        let lines = match self.registers.get(register) {
+            Some(buffer) if !buffer.is_empty() => buffer.clone(),
+            _ => return Err(Error::new(ErrorType::EmptyRegister(register)))
+        };
+
+        let count = self.doc_mut().insert_lines(line, lines);
+        self.doc_mut().set_current_line(line + count);
+        Ok(())
     }
 
-}
+    /// Resolves `target` to a document index and the line to insert after.
+    fn resolve_target(&self, target: &pos::Target) -> Result<(usize, usize)> {
+        let index = match target.buffer {
+            Some(n) => self.buffer_index(n)?,
+            None => self.current_document
+        };
 
-impl <'a> pos::Converter<&'a pos::Pos, usize> for Rsed {
-    fn convert(&self, pos: &pos::Pos) -> usize {
-        match *pos {
-            pos::Pos::Line(n) => n,
-            pos::Pos::Current => self.current_line,
-            pos::Pos::End => self.current_buffer.len()
+        let document = &self.documents[index];
+        let line = self.resolve_pos(document, &target.pos)?;
+
+        if document.buffer().is_out_of_bounds(line) {
+            return Err(Error::unknown("invalid destination"));
         }
+
+        Ok((index, line))
     }
-}
 
+    fn copied_lines(&self, r: &pos::Range) -> Result<(ops::Range<usize>, Vec<String>)> {
+        let range = r.to_range(self)?;
+
+        if self.doc().buffer().is_range_out_of_bounds(&range) {
+            return Err(Error::new(ErrorType::InvalidRange(Box::new(r.clone()))));
+        }
+
+        let lines = self.doc().lines(&range).map(str::to_string).collect();
+        Ok((range, lines))
+    }
+
+    fn transfer(&mut self, r: pos::Range, target: pos::Target) -> Result<()> {
+        let (_, lines) = self.copied_lines(&r)?;
+        let (index, line) = self.resolve_target(&target)?;
+
+        let document = &mut self.documents[index];
+        let count = document.insert_lines(line, lines);
+        document.set_current_line(line + count);
+
+        Ok(())
+    }
+
+    fn move_lines(&mut self, r: pos::Range, target: pos::Target) -> Result<()> {
+        let (range, lines) = self.copied_lines(&r)?;
+        let (index, line) = self.resolve_target(&target)?;
+        let count = lines.len();
+
+        if index != self.current_document {
+            self.doc_mut().delete_lines(&range);
+
+            let next_line = cmp::min(range.start + 1, self.doc().len());
+            self.doc_mut().set_current_line(next_line);
+
+            let document = &mut self.documents[index];
+            document.insert_lines(line, lines);
+            document.set_current_line(line + count);
+        } else if line >= range.end {
+            self.doc_mut().insert_lines(line, lines);
+            self.doc_mut().delete_lines(&range);
+            self.doc_mut().set_current_line(line);
+        } else if line <= range.start {
+            self.doc_mut().delete_lines(&range);
+            self.doc_mut().insert_lines(line, lines);
+            self.doc_mut().set_current_line(line + count);
+        } else {
+            return Err(Error::unknown("invalid destination"));
+        }
+
+        Ok(())
+    }
+
+    fn print_range(&mut self, r: pos::Range, option: ui::PrintOption) -> Result<()> {
+       let range = r.to_range(self)?;
+
+       if self.doc().buffer().is_range_out_of_bounds(&range) {
+           return Err(Error::new(ErrorType::InvalidRange(Box::new(r))));
+       }
+
+       let document = &self.documents[self.current_document];
+       let mut model = ui::DisplayModel::new( document.buffer(), range, option );
+       model.set_highlighter( highlighter_for(&self.highlighter, document), document.highlight_states() );
+
+       self.ui.display( model );
+
+       Ok(())
+    }
+
+    /// Prints every line of `r` matching `pattern` with its number and the
+    /// matches marked, plus `context` lines around each of them. The last
+    /// matching line becomes the current line.
+    fn list_matches(&mut self, r: pos::Range, pattern: String, context: usize) -> Result<()> {
+        let range = r.to_range(self)?;
+
+        if self.doc().buffer().is_range_out_of_bounds(&range) {
+            return Err(Error::new(ErrorType::InvalidRange(Box::new(r))));
+        }
+
+        let regex = self.search.compile(&pattern)?;
+        let buffer = self.documents[self.current_document].buffer();
+        let mut sections: Vec<ui::Section> = Vec::new();
+        let mut last_match = None;
+
+        for (n, line) in buffer.lines(&range).enumerate() {
+            if n % signal::CHECK_INTERVAL == 0 {
+                signal::check_interrupt()?;
+            }
+
+            let spans: Vec<_> = regex.find_iter(line).map(|(start, end)| start..end).collect();
+            if spans.is_empty() {
+                continue;
+            }
+
+            let index = range.start + n;
+            let start = index.saturating_sub(context);
+            let end = cmp::min(index + context + 1, buffer.len());
+
+            let overlaps = sections.last().is_some_and(|section| section.range().end >= start);
+            if !overlaps {
+                sections.push(ui::Section::new(start..end));
+            }
+
+            let section = sections.last_mut().unwrap();
+            section.extend_to(end);
+            section.highlight(index, spans);
+            last_match = Some(index + 1);
+        }
+
+        let last_match = last_match.ok_or(Error::new(ErrorType::NoMatch))?;
+
+        let mut model = ui::DisplayModel::with_sections(buffer, sections, ui::PrintOption::Numbered);
+        let document = &self.documents[self.current_document];
+        model.set_highlighter(highlighter_for(&self.highlighter, document), document.highlight_states());
+        self.ui.display(model);
+        self.doc_mut().set_current_line(last_match);
+
+        Ok(())
+    }
+
+    /// Replaces matches of the pattern in every line of `r`. Replacements
+    /// with newlines split lines, the last line written becomes the current
+    /// line. It's an error if no line matched.
+    fn substitute(&mut self, r: pos::Range, substitution: cmd::Substitution) -> Result<()> {
+        let range = r.to_range(self)?;
+
+        if self.doc().buffer().is_range_out_of_bounds(&range) {
+            return Err(Error::new(ErrorType::InvalidRange(Box::new(r))));
+        }
+
+        let replacement = if substitution.replacement == "%" {
+            match self.last_replacement {
+                Some(ref last) => last.clone(),
+                None => return Err(Error::new(ErrorType::NoPreviousReplacement))
+            }
+        } else {
+            substitution.replacement
+        };
+
+        let regex = self.search.compile(&substitution.pattern)?;
+        let mut changes = Vec::new();
+
+        for (n, line) in self.doc().lines(&range).enumerate() {
+            if n % signal::CHECK_INTERVAL == 0 {
+                signal::check_interrupt()?;
+            }
+
+            if let Some(text) = search::substitute(&regex, line, &replacement, substitution.global, substitution.nth) {
+                changes.push((range.start + n, text));
+            }
+        }
+
+        self.last_replacement = Some(replacement);
+
+        let last = match changes.last() {
+            Some(&(index, _)) => index,
+            None => return Err(Error::new(ErrorType::NoMatch))
+        };
+
+        // from the bottom up, so that split lines don't move the ones left
+        let mut added = 0;
+        for (index, text) in changes.into_iter().rev() {
+            let mut lines = text.split('\n').map(str::to_string);
+            let first = lines.next().unwrap_or_default();
+            self.doc_mut().replace_line(index, first);
+            added += self.doc_mut().insert_lines(index + 1, lines);
+        }
+
+        self.doc_mut().set_current_line(last + 1 + added);
+        Ok(())
+    }
+
+    /// Runs `commands` at every line of `r` matching `pattern`, or not
+    /// matching it if `invert`. All lines are marked before the commands
+    /// run, an empty command list prints them.
+    fn global(&mut self, r: pos::Range, pattern: String, invert: bool, commands: Vec<String>) -> Result<()> {
+        if self.in_global {
+            return Err(Error::unknown("nested global command"));
+        }
+
+        let range = r.to_range(self)?;
+
+        if self.doc().buffer().is_range_out_of_bounds(&range) {
+            return Err(Error::new(ErrorType::InvalidRange(Box::new(r))));
+        }
+
+        let mut parser = ui::Ui::new();
+        let mut actions = Vec::new();
+        for command in &commands {
+            if let Some(action) = parser.parse_input(command)? {
+                parser.follow(&action);
+                actions.push(action);
+            }
+        }
+
+        // the `.` ending text inserted by the last command may be left out
+        if parser.is_insert_mode() {
+            actions.push(ui::Action::InsertEnd);
+        }
+        if actions.is_empty() {
+            let print = Cmd::Print(pos::Range::current_line(), ui::PrintOption::Normal);
+            actions.push(ui::Action::Command(cmd::Command::new(print)));
+        }
+
+        let regex = self.search.compile(&pattern)?;
+        let mut lines = Vec::new();
+
+        for (n, line) in self.doc().lines(&range).enumerate() {
+            if n % signal::CHECK_INTERVAL == 0 {
+                signal::check_interrupt()?;
+            }
+
+            if regex.is_match(line) != invert {
+                lines.push(range.start + n + 1);
+            }
+        }
+
+        self.in_global = true;
+        let result = self.visit(lines, &actions);
+        self.in_global = false;
+
+        result
+    }
+
+    /// Prints a window of lines starting at `r`, or after the current line,
+    /// and makes the last one printed the current line. `lines` changes the
+    /// window size for this and later scrolls.
+    fn scroll(&mut self, r: Option<pos::Range>, lines: Option<usize>, option: ui::PrintOption) -> Result<()> {
+        if let Some(n) = lines {
+            self.window = n;
+        }
+
+        let start = match r {
+            Some(r) => self.convert(&pos::Pos::from(r))?,
+            None => self.doc().current_line() + 1
+        };
+
+        let len = self.doc().len();
+        if start == 0 || start > len {
+            return Err(Error::new(ErrorType::InvalidRange(Box::new(pos::Range::Line(pos::Pos::Line(start))))));
+        }
+
+        let end = cmp::min(start + cmp::max(self.window, 1) - 1, len);
+        self.print_range(pos::Range::Range(pos::Pos::Line(start), pos::Pos::Line(end)), option)?;
+        self.doc_mut().set_current_line(end);
+
+        Ok(())
+    }
+
+    fn visual(&mut self) -> Result<()> {
+        if !term::is_interactive() {
+            return Err(Error::unknown("visual mode needs a terminal"));
+        }
+
+        visual::run(self.doc_mut())
+    }
+
+    fn list_history(&mut self) -> Result<()> {
+        for (index, command) in self.history.commands().iter().enumerate() {
+            self.ui.print(format!("{}\t{}", index + 1, command));
+        }
+
+        Ok(())
+    }
+
+    fn rerun(&mut self, n: usize) -> Result<()> {
+        match self.history.get(n).cloned() {
+            Some(command) => self.handle_command(command),
+            None => Err(Error::new(ErrorType::UnknownHistoryEntry(n)))
+        }
+    }
+
+    /// Repeats the last change on the range `r`, or on as many lines as it
+    /// applied to from the line `r` or the current line. Inserted text is
+    /// inserted again without going through insert mode.
+    fn repeat_change(&mut self, r: Option<pos::Range>) -> Result<()> {
+        let change = match self.history.last_change() {
+            Some(change) => change.clone(),
+            None => return Err(Error::new(ErrorType::NoPreviousChange))
+        };
+
+        let range = match r {
+            Some(r @ pos::Range::Range(_, _)) => r,
+            Some(pos::Range::Line(p)) => change.range_at(self.convert(&p)?),
+            None => change.range_at(self.doc().current_line())
+        };
+        let command = change.at(range);
+
+        let (r, text) = match (command.cmd.clone(), change.text()) {
+            (Cmd::EnterInsertMode(r), Some(text)) => (r, text.clone()),
+            _ => return self.handle_command(command)
+        };
+
+        let line = self.convert(&pos::Pos::from(r))?;
+        if self.doc().buffer().is_out_of_bounds(line) {
+            return Err(Error::unknown("invalid line"));
+        }
+
+        let count = self.doc_mut().insert_lines(line, text.clone());
+        self.doc_mut().set_current_line(line + count);
+
+        self.history.add(command, change.lines());
+        self.history.set_change_text(text);
+        Ok(())
+    }
+
+    /// Reads the configuration file at `path`. It may only contain `:set`
+    /// and `:alias` commands, blank lines and comments starting with `#`.
+    pub fn load_config<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+
+        for (n, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let result = line.parse::<cmd::Command>().and_then(|command| match command.cmd {
+                Cmd::Set(Some(name), Some(value)) => self.set_option(&name, &value),
+                Cmd::DefineAlias(name, body) => {
+                    self.define_alias(name, body);
+                    Ok(())
+                },
+                _ => Err(Error::unknown("only ':set name=value' and ':alias' are allowed"))
+            });
+
+            if let Err(e) = result {
+                return Err(Error::unknown(&format!("{}:{}: {}", path.display(), n + 1, e)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads `~/.rsedrc` and then `.rsedrc` in the current directory, those
+    /// that exist.
+    pub fn load_config_files(&mut self) -> Result<()> {
+        for path in config::user_config_file().into_iter().chain(config::project_config_file()) {
+            if path.is_file() {
+                self.load_config(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current value of option `name`, as `:set` shows it.
+    pub fn option(&self, name: &str) -> Result<String> {
+        match name {
+            "prompt" => Ok(self.ui.prompt().to_string()),
+            "verbose" => Ok(config::format_bool(self.ui.is_verbose()).to_string()),
+            "color" => Ok(config::format_bool(self.ui.is_color()).to_string()),
+            "dialect" => Ok(self.search.dialect().to_string()),
+            "backup" => Ok(self.backup.to_string()),
+            "diffcontext" => Ok(self.diff_context.to_string()),
+            _ => Err(Error::new(ErrorType::UnknownOption(name.to_string())))
+        }
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<()> {
+        match name {
+            "prompt" => self.ui.set_prompt(value.to_string()),
+            "verbose" => self.ui.set_verbose(config::parse_bool(value)?),
+            "color" => self.ui.set_color(config::parse_bool(value)? && self.terminal),
+            "dialect" => self.search.set_dialect(value.parse()?),
+            "backup" => self.backup = value.parse()?,
+            "diffcontext" => self.diff_context = value.parse().map_err(|_| {
+                Error::detailed(ErrorType::ParseError, format!("expected a number of lines, not '{}'", value))
+            })?,
+            _ => return Err(Error::new(ErrorType::UnknownOption(name.to_string())))
+        }
+
+        Ok(())
+    }
+
+    fn show_option(&mut self, name: &str) -> Result<()> {
+        let value = self.option(name)?;
+        self.ui.print(format!("{}={}", name, value));
+        Ok(())
+    }
+
+    fn list_options(&mut self) -> Result<()> {
+        for name in config::OPTIONS {
+            self.show_option(name)?;
+        }
+
+        Ok(())
+    }
+
+    fn list_aliases(&mut self) -> Result<()> {
+        for (name, body) in &self.aliases {
+            self.ui.print(format!("{}\t{}", name, body));
+        }
+
+        Ok(())
+    }
+
+    fn define_alias(&mut self, name: String, body: String) {
+        self.aliases.insert(name, body);
+    }
+
+    /// Runs the input lines an alias stands for, as if they were typed.
+    fn run_alias(&mut self, name: &str) -> Result<()> {
+        let body = match self.aliases.get(name) {
+            Some(body) => body.clone(),
+            None => return Err(Error::new(ErrorType::UnknownAlias(name.to_string())))
+        };
+
+        if self.expanding.iter().any(|alias| alias == name) {
+            return Err(Error::unknown(&format!("alias ':{}' runs itself", name)));
+        }
+
+        // parse everything first, so that a typo doesn't leave a half done
+        // sequence of changes
+        let mut parser = ui::Ui::new();
+        let mut actions = Vec::new();
+        for line in config::split_alias(&body) {
+            if let Some(action) = parser.parse_input(&line)? {
+                parser.follow(&action);
+                actions.push(action);
+            }
+        }
+        if parser.is_continued() {
+            return Err(Error::detailed(ErrorType::Unfinished, format!("alias ':{}' ends in an unfinished command", name)));
+        }
+
+        self.expanding.push(name.to_string());
+        let result = self.play(&actions);
+        self.expanding.pop();
+
+        result
+    }
+
+    fn list_macros(&mut self) -> Result<()> {
+        let mut lines = Vec::new();
+
+        for (name, actions) in self.macros.slots() {
+            lines.push(format!("[{}]", name));
+            lines.extend(actions.iter().map(|action| action.to_string()));
+        }
+
+        for line in lines {
+            self.ui.print(line);
+        }
+
+        Ok(())
+    }
+
+    /// Replays macro `name` once for every line of `r`, starting at that
+    /// line, or `count` times, each time starting at the line after the one
+    /// the previous run ended on.
+    fn run_macro(&mut self, r: Option<pos::Range>, name: char, count: usize) -> Result<()> {
+        let actions = match self.macros.get(name) {
+            Some(actions) => actions.clone(),
+            None => return Err(Error::new(ErrorType::UnknownMacro(name)))
+        };
+
+        self.macros.start_playing(name)?;
+        let result = self.replay(r, &actions, count);
+        self.macros.stop_playing();
+
+        result
+    }
+
+    fn replay(&mut self, r: Option<pos::Range>, actions: &[ui::Action], count: usize) -> Result<()> {
+        if let Some(r) = r {
+            let range = r.to_range(self)?;
+
+            if self.doc().buffer().is_range_out_of_bounds(&range) {
+                return Err(Error::new(ErrorType::InvalidRange(Box::new(r))));
+            }
+
+            return self.visit(range.start + 1..range.end + 1, actions);
+        }
+
+        for n in 0..count {
+            if n > 0 {
+                let next_line = self.doc().current_line() + 1;
+                if self.doc().buffer().is_out_of_bounds(next_line) {
+                    return Err(Error::unknown("invalid line"));
+                }
+                self.doc_mut().set_current_line(next_line);
+            }
+
+            self.play(actions)?;
+        }
+
+        Ok(())
+    }
+
+    /// Plays `actions` starting at each of `lines` in turn. The lines are
+    /// tracked through the edits the actions make, switching to another
+    /// buffer ends the visit.
+    fn visit<I: IntoIterator<Item=usize>>(&mut self, lines: I, actions: &[ui::Action]) -> Result<()> {
+        let document = self.current_document;
+        self.doc_mut().start_visit(lines);
+
+        let mut result = Ok(());
+        while self.current_document == document {
+            match self.doc_mut().next_visited() {
+                Some(line) => self.doc_mut().set_current_line(line),
+                None => break
+            }
+
+            result = self.play(actions);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if let Some(doc) = self.documents.get_mut(document) {
+            doc.end_visit();
+        }
+        result
+    }
+
+    /// Handles recorded `actions` as if they were typed, stopping at the
+    /// first error. In a `g` command list, `s` finding nothing on the line
+    /// isn't an error.
+    fn play(&mut self, actions: &[ui::Action]) -> Result<()> {
+        for action in actions {
+            signal::check_interrupt()?;
+            match self.handle_action(action.clone()) {
+                Err(ref e) if self.in_global && matches!(e.error, ErrorType::NoMatch) &&
+                              matches!(*action, ui::Action::Command(cmd::Command { cmd: Cmd::Substitute(..), .. })) => (),
+                result => result?
+            }
+        }
+
+        Ok(())
+    }
+
+    fn jump_to(&mut self, r: pos::Range) -> Result<()> {
+        let line = self.convert( &pos::Pos::from(r) )?;
+        self.doc_mut().set_current_line(line);
+        self.print_range( pos::Range::current_line(), ui::PrintOption::Normal)
+    }
+
+    fn jump_next(&mut self) -> Result<()> {
+        let next_line = self.doc().current_line() + 1;
+
+        if self.doc().buffer().is_out_of_bounds(next_line) {
+            return Err(Error::unknown("invalid line"));
+        }
+
+        self.doc_mut().set_current_line(next_line);
+        self.print_range( pos::Range::current_line(), ui::PrintOption::Normal)
+    }
+
+}
+
+impl pos::Converter<&pos::Pos, Result<usize>> for Rsed {
+    fn convert(&self, pos: &pos::Pos) -> Result<usize> {
+        self.resolve_pos(self.doc(), pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rsed_with(lines: &[&str]) -> Rsed {
+        let mut rsed = Rsed::new();
+        rsed.execute("i").unwrap();
+        for line in lines {
+            rsed.execute(line).unwrap();
+        }
+        rsed.execute(".").unwrap();
+        rsed
+    }
+
+    #[test]
+    fn execute_returns_output() {
+        let mut rsed = rsed_with(&["one", "two", "three"]);
+        let output = rsed.execute("2,3p").unwrap();
+        assert_eq!(output.lines(), &["two", "three"]);
+        assert!(rsed.execute("1d").unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors_have_messages() {
+        let message = |rsed: &mut Rsed, input: &str| rsed.execute(input).unwrap_err().to_string();
+        let mut rsed = Rsed::new();
+
+        assert_eq!(message(&mut rsed, "."), "invalid address '.'");
+        assert_eq!(message(&mut rsed, "&"), "no previous change");
+        assert_eq!(message(&mut rsed, ":nothing"), "unknown command ':nothing'");
+        assert_eq!(message(&mut rsed, ":set nothing"), "unknown option 'nothing'");
+        assert_eq!(message(&mut rsed, ":encoding nothing"), "unknown encoding 'nothing'");
+
+        let mut rsed = rsed_with(&["one"]);
+        assert_eq!(message(&mut rsed, "'a"), "mark 'a' isn't set");
+        assert_eq!(message(&mut rsed, "1x\"q"), "register 'q' is empty");
+        assert_eq!(message(&mut rsed, "/two/"), "no match");
+        assert_eq!(message(&mut rsed, "1s/one/%/"), "no previous replacement");
+        assert_eq!(message(&mut rsed, "@m"), "macro 'm' is empty");
+        assert_eq!(message(&mut rsed, "b 3"), "no buffer 3");
+    }
+
+    #[test]
+    fn failed_command_keeps_its_output() {
+        let mut rsed = rsed_with(&["one", "two"]);
+        rsed.execute(":alias x 1p;2p;9p;1p").unwrap();
+
+        let error = rsed.execute(":x").unwrap_err();
+        assert_eq!(error.output().lines(), &["one", "two"]);
+        match *error.error_type() {
+            ErrorType::InvalidRange(_) => (),
+            ref other => panic!("unexpected error {:?}", other)
+        }
+
+        // the next command starts with empty output
+        assert_eq!(rsed.execute("1p").unwrap().lines(), &["one"]);
+    }
+
+    #[test]
+    fn color_option_needs_a_terminal() {
+        let mut rsed = rsed_with(&["one"]);
+
+        rsed.execute(":set color=on").unwrap();
+        assert_eq!(rsed.option("color").unwrap(), "off");
+        assert_eq!(rsed.execute("1p").unwrap().lines(), &["one"]);
+
+        rsed.set_terminal(true);
+        rsed.execute(":set color=on").unwrap();
+        assert_eq!(rsed.option("color").unwrap(), "on");
+
+        // forcing colours doesn't ask
+        rsed.set_terminal(false);
+        rsed.set_color(true);
+        assert_eq!(rsed.option("color").unwrap(), "on");
+    }
+
+    /// Colours every line it highlights as a keyword.
+    struct Keywords;
+
+    impl highlight::Highlighter for Keywords {
+        fn highlight_line(&self, line: &str, _state: &mut highlight::State) -> Vec<highlight::Token> {
+            vec![highlight::Token::new(0..line.len(), highlight::Style::Keyword)]
+        }
+    }
+
+    #[test]
+    fn custom_highlighter() {
+        let mut rsed = rsed_with(&["one"]);
+        rsed.set_color(true);
+        assert_eq!(rsed.execute("1p").unwrap().lines(), &["one"]);
+
+        rsed.set_highlighter(Some(Rc::new(Keywords)));
+        assert_eq!(rsed.execute("1p").unwrap().lines(), &["\x1b[35mone\x1b[0m"]);
+
+        rsed.set_color(false);
+        assert_eq!(rsed.execute("1p").unwrap().lines(), &["one"]);
+    }
+
+    fn all_lines(rsed: &mut Rsed) -> Vec<String> {
+        rsed.execute("1,$p").unwrap().lines().to_vec()
+    }
+
+    #[test]
+    fn named_registers() {
+        let mut rsed = rsed_with(&["one", "two", "three"]);
+        rsed.execute("1y\"a").unwrap();
+        rsed.execute("3d\"b").unwrap();
+        rsed.execute("2y").unwrap();
+
+        rsed.execute("0x\"b").unwrap();
+        rsed.execute("$x\"a").unwrap();
+        rsed.execute("$x").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["three", "one", "two", "one", "two"]);
+        assert!(rsed.execute("1x\"c").is_err());
+
+        // an uppercase name appends, and the unnamed register gets it all
+        rsed.execute("2,3y\"A").unwrap();
+        rsed.execute("0x").unwrap();
+        assert_eq!(&all_lines(&mut rsed)[..3], &["one", "one", "two"]);
+        rsed.execute("1,$d").unwrap();
+        rsed.execute("0x\"A").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["one", "one", "two"]);
+    }
+
+    #[test]
+    fn list_matches_with_context() {
+        let mut rsed = rsed_with(&["a", "b", "x1", "c", "d", "e", "f", "x2", "g"]);
+        assert_eq!(rsed.execute("L/x/1").unwrap().lines(),
+                   &["2\tb", "3\tx1", "\t^", "4\tc", "--", "7\tf", "8\tx2", "\t^", "9\tg"]);
+        assert_eq!(rsed.execute(".p").unwrap().lines(), &["x2"]);
+
+        // matches whose context touches share a section, which may reach
+        // past the addressed lines
+        assert_eq!(rsed.execute("1,4L/[ac]/1").unwrap().lines(),
+                   &["1\ta", "\t^", "2\tb", "3\tx1", "4\tc", "\t^", "5\td"]);
+        assert!(matches!(rsed.execute("L/x1x2/").map_err(|e| e.error), Err(ErrorType::NoMatch)));
+    }
+
+    #[test]
+    fn scroll_keeps_its_window_size() {
+        let lines: Vec<String> = (1..11).map(|n| n.to_string()).collect();
+        let lines: Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+        let mut rsed = rsed_with(&lines);
+
+        assert_eq!(rsed.execute("2z3").unwrap().lines(), &["2", "3", "4"]);
+        assert_eq!(rsed.execute(".p").unwrap().lines(), &["4"]);
+
+        // later scrolls go on after the current line with the same size
+        assert_eq!(rsed.execute("z").unwrap().lines(), &["5", "6", "7"]);
+        assert_eq!(rsed.execute("zn").unwrap().lines(), &["8\t8", "9\t9", "10\t10"]);
+        assert_eq!(rsed.execute(".p").unwrap().lines(), &["10"]);
+        assert!(rsed.execute("z").is_err());
+
+        assert_eq!(rsed.execute("9z5").unwrap().lines(), &["9", "10"]);
+    }
+
+    #[test]
+    fn history_redo_and_repeat() {
+        let mut rsed = rsed_with(&["a a", "b a", "c a", "d a"]);
+        rsed.execute("1s/a/x/").unwrap();
+        rsed.execute("2p").unwrap();
+        assert_eq!(rsed.execute(":history").unwrap().lines(), &["1\t.i", "2\t1s/a/x/", "3\t2p"]);
+
+        // & repeats the last s on another line, and is recorded as that s
+        rsed.execute("3&").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["x a", "b a", "c x", "d a"]);
+
+        rsed.execute(":redo 2").unwrap();
+        assert_eq!(rsed.execute("1p").unwrap().lines(), &["x x"]);
+        assert_eq!(&rsed.execute(":history").unwrap().lines()[3..], &["4\t3s/a/x/", "5\t1,$p", "6\t1s/a/x/", "7\t1p"]);
+        assert!(rsed.execute(":redo 99").is_err());
+    }
+
+    #[test]
+    fn recording_twice_is_an_error() {
+        let mut rsed = rsed_with(&["one"]);
+        rsed.execute(":record a").unwrap();
+        rsed.execute("1p").unwrap();
+
+        assert!(rsed.execute(":record b").is_err());
+        rsed.execute(":record").unwrap();
+        assert_eq!(rsed.execute("@a").unwrap().lines(), &["one"]);
+        assert!(rsed.execute("@b").is_err());
+    }
+
+    #[test]
+    fn range_replay_follows_edits() {
+        let mut rsed = rsed_with(&["a", "b", "c", "d", "e"]);
+        rsed.execute("1").unwrap();
+        rsed.execute(":record d").unwrap();
+        rsed.execute(".d").unwrap();
+        rsed.execute(":record").unwrap();
+
+        // each line is deleted once, where it moved to
+        rsed.execute("1,3@d").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["e"]);
+
+        let mut rsed = rsed_with(&["a", "b", "c"]);
+        rsed.execute("1").unwrap();
+        rsed.execute(":record t").unwrap();
+        rsed.execute(".t.").unwrap();
+        rsed.execute(":record").unwrap();
+
+        // copies don't shift the lines still to be visited
+        rsed.execute("3,4@t").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["a", "a", "b", "b", "c", "c"]);
+    }
+
+    #[test]
+    fn substitute_and_global() {
+        let mut rsed = rsed_with(&["a1", "b2", "a3"]);
+
+        rsed.execute("1,$s/\\([a-z]\\)\\([0-9]\\)/\\2&/").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["1a1", "2b2", "3a3"]);
+        assert!(rsed.execute("1,$s/x/y/").is_err());
+
+        // in a command list, `s` finding nothing on a line isn't an error
+        rsed.execute("g/a/s/3/x/").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["1a1", "2b2", "xa3"]);
+
+        rsed.execute("v/b/d").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["2b2"]);
+        assert!(rsed.execute("g/b/g/b/p").is_err());
+    }
+
+    #[test]
+    fn continued_commands() {
+        let mut rsed = rsed_with(&["one two", "three"]);
+
+        // an escaped newline in the replacement splits the line
+        assert!(rsed.execute("1s/ /\\").unwrap().is_empty());
+        assert_eq!(rsed.execute("/p").unwrap().lines(), &["two"]);
+        assert_eq!(all_lines(&mut rsed), &["one", "two", "three"]);
+
+        rsed.execute("g/o/s/o/0/\\").unwrap();
+        assert_eq!(rsed.execute("p").unwrap().lines(), &["0ne", "tw0"]);
+        assert_eq!(all_lines(&mut rsed), &["0ne", "tw0", "three"]);
+    }
+
+    #[test]
+    fn commands_continued_over_several_lines() {
+        let mut rsed = rsed_with(&["a b c", "d"]);
+
+        // nothing runs until the last line of the command
+        assert!(rsed.execute("1s/ /\\").unwrap().is_empty());
+        assert!(rsed.execute("-\\").unwrap().is_empty());
+        assert!(rsed.execute("/g").unwrap().is_empty());
+        assert_eq!(all_lines(&mut rsed), &["a", "-", "b", "-", "c", "d"]);
+
+        rsed.execute("g/^[a-c]$/s/$/!/\\").unwrap();
+        rsed.execute("s/^/[/\\").unwrap();
+        rsed.execute("s/$/]/").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["[a!]", "-", "[b!]", "-", "[c!]", "d"]);
+
+        // the same lines fed to the main loop, with an insert in the list
+        let mut rsed = rsed_with(&["x", "y"]);
+        run_lines(&mut rsed, "g/./i\\\n>\\\n>>\\\n.\nq\n");
+        assert_eq!(all_lines(&mut rsed), &["x", ">", ">>", "y", ">", ">>"]);
+    }
+
+    #[test]
+    fn escaped_dot_inserts_a_dot() {
+        let mut rsed = rsed_with(&["\\.", "\\\\.", "..", "\\x"]);
+        assert_eq!(all_lines(&mut rsed), &[".", "\\.", "..", "\\x"]);
+
+        // in a command list the terminating `.` may be left out, an escaped
+        // one is still text
+        rsed.execute("g/^\\.$/i\\").unwrap();
+        rsed.execute("\\.").unwrap();
+        assert_eq!(all_lines(&mut rsed), &[".", ".", "\\.", "..", "\\x"]);
+    }
+
+    #[test]
+    fn continued_commands_in_macros() {
+        let temp = testutil::TempFile::new("lib-macros", b"");
+        let mut rsed = rsed_with(&["a", "b"]);
+        rsed.execute(":record m").unwrap();
+        rsed.execute("g/./s/$/!/\\").unwrap();
+        rsed.execute("s/^/-/").unwrap();
+        rsed.execute(":record").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["-a!", "-b!"]);
+        rsed.execute(&format!(":macros save {}", temp.path().display())).unwrap();
+
+        let mut rsed = rsed_with(&["c"]);
+        rsed.execute(&format!(":macros load {}", temp.path().display())).unwrap();
+        rsed.execute("@m").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["-c!"]);
+    }
+
+    #[test]
+    fn diff_against_the_file_and_other_buffers() {
+        let temp = testutil::TempFile::new("lib-diff", b"one\ntwo\n");
+        let other = testutil::TempFile::new("lib-diff-other", b"one\n");
+        let name = temp.path().display().to_string();
+
+        let mut rsed = Rsed::new();
+        rsed.execute(&format!("e {}", name)).unwrap();
+        assert!(rsed.execute(":diff").unwrap().is_empty());
+
+        rsed.execute("1d").unwrap();
+        assert_eq!(rsed.execute(":diff -U 0").unwrap().lines(),
+                   &[format!("--- {}", name), format!("+++ {}", name), "@@ -1 +0,0 @@".to_string(), "-one".to_string()]);
+
+        rsed.execute(&format!("B {}", other.path().display())).unwrap();
+        assert_eq!(&rsed.execute(":diff #1").unwrap().lines()[2..], &["@@ -1 +1 @@", "-two", "+one"]);
+    }
+
+    #[test]
+    fn from_path_opens_the_file() {
+        let temp = testutil::TempFile::new("lib-from-path", b"one\ntwo\n");
+        let mut rsed = Rsed::from_path(temp.path()).unwrap();
+        assert_eq!(all_lines(&mut rsed), &["one", "two"]);
+
+        rsed.execute("1d").unwrap();
+        rsed.execute("w").unwrap();
+        assert_eq!(temp.contents(), b"two\n");
+    }
+
+    #[test]
+    fn buffers_are_opened_switched_and_closed() {
+        let first = testutil::TempFile::new("lib-buffers-first", b"one\n");
+        let second = testutil::TempFile::new("lib-buffers-second", b"two\n");
+
+        let mut rsed = Rsed::new();
+        rsed.execute(&format!("e {}", first.path().display())).unwrap();
+        rsed.execute(&format!("B {}", second.path().display())).unwrap();
+        assert_eq!(all_lines(&mut rsed), &["two"]);
+
+        rsed.execute("1s/two/2/").unwrap();
+        assert_eq!(rsed.execute("b").unwrap().lines(),
+                   &[format!("1  \t{}", first.path().display()), format!("2*+\t{}", second.path().display())]);
+
+        rsed.execute("b 1").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["one"]);
+        assert!(rsed.execute("b 3").is_err());
+
+        // closing a modified buffer drops its changes
+        rsed.execute("bd 2").unwrap();
+        assert_eq!(rsed.execute("b").unwrap().lines(), &[format!("1* \t{}", first.path().display())]);
+        assert_eq!(second.contents(), b"two\n");
+        assert!(rsed.execute("bd").is_err());
+    }
+
+    #[test]
+    fn lines_are_copied_and_moved_between_buffers() {
+        let other = testutil::TempFile::new("lib-buffers-other", b"x\ny\n");
+
+        let mut rsed = rsed_with(&["a", "b", "c"]);
+        rsed.execute(&format!("B {}", other.path().display())).unwrap();
+        rsed.execute("b 1").unwrap();
+
+        rsed.execute("1t2:1").unwrap();
+        rsed.execute("2,3m2:$").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["a"]);
+        assert_eq!(rsed.execute(".p").unwrap().lines(), &["a"]);
+
+        rsed.execute("b 2").unwrap();
+        assert_eq!(all_lines(&mut rsed), &["x", "a", "y", "b", "c"]);
+        assert_eq!(rsed.execute(".p").unwrap().lines(), &["c"]);
+    }
+
+    #[test]
+    fn config_stops_at_a_bad_line() {
+        let temp = testutil::TempFile::new("lib-config", b"# options\n:set diffcontext=1\n\n:alias top 1p\n1d\n:set prompt=>\n");
+        let mut rsed = rsed_with(&["one", "two"]);
+
+        let e = rsed.load_config(temp.path()).unwrap_err();
+        assert_eq!(e.to_string(), format!("{}:5: only ':set name=value' and ':alias' are allowed", temp.path().display()));
+        assert_eq!(rsed.option("diffcontext").unwrap(), "1");
+        assert_eq!(rsed.option("prompt").unwrap(), "");
+        assert_eq!(rsed.execute(":top").unwrap().lines(), &["one"]);
+        assert_eq!(all_lines(&mut rsed), &["one", "two"]);
+    }
+
+    #[test]
+    fn aliases_running_themselves_are_an_error() {
+        let mut rsed = rsed_with(&["one"]);
+        rsed.execute(":alias ping 1s/$/!/; :pong").unwrap();
+        rsed.execute(":alias pong :ping").unwrap();
+
+        let e = rsed.execute(":ping").unwrap_err();
+        assert_eq!(e.to_string(), "alias ':ping' runs itself");
+        // the alias is not undone, but it doesn't run forever either
+        assert_eq!(all_lines(&mut rsed), &["one!"]);
+
+        rsed.execute(":alias pong 1p").unwrap();
+        assert_eq!(rsed.execute(":ping").unwrap().lines(), &["one!!"]);
+    }
+
+    #[test]
+    fn interrupt_abandons_a_global_command() {
+        let lines: Vec<String> = (0..10000).map(|n| n.to_string()).collect();
+        let mut rsed = Rsed::new();
+        rsed.doc_mut().insert_lines(0, lines.clone());
+
+        signal::raise();
+        assert!(matches!(rsed.execute("g/./s/$/!/").map_err(|e| e.error), Err(ErrorType::Interrupted)));
+        assert!(signal::take_interrupt());
+        assert_eq!(all_lines(&mut rsed), lines);
+
+        rsed.execute("g/0$/s/$/!/").unwrap();
+        assert_eq!(rsed.execute("9990,9991p").unwrap().lines(), &["9989", "9990!"]);
+    }
+
+    #[test]
+    fn interrupt_ends_insert_mode() {
+        // `None` stands for Ctrl-C pressed while waiting for input
+        let mut input = vec![Some("1i"), Some("one"), None, Some("1,$p"), Some("q")].into_iter();
+        let mut output = Vec::new();
+
+        let mut rsed = rsed_with(&["last"]);
+        rsed.run_with(|_| match input.next() {
+            Some(Some(line)) => Ok(Some(line.to_string())),
+            Some(None) => {
+                signal::raise();
+                Err(Error::new(ErrorType::Interrupted))
+            },
+            None => Ok(None)
+        }, &mut output, false).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "?\nlast\none\n");
+        assert!(!signal::interrupt_pending());
+    }
+
+    fn run_lines(rsed: &mut Rsed, input: &str) {
+        rsed.run(&mut io::Cursor::new(input.as_bytes()), &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn quitting_removes_swap_files() {
+        let temp = testutil::TempFile::new("lib-swap", b"one\ntwo\n");
+        let swap = swap::swap_path(temp.path());
+
+        let mut rsed = Rsed::new();
+        run_lines(&mut rsed, &format!("e {}\n1d\n1p\nq\n", temp.path().display()));
+        assert!(!swap.exists());
+        assert_eq!(temp.contents(), b"one\ntwo\n");
+    }
+
+    #[test]
+    fn writing_over_outside_changes_needs_force() {
+        let temp = testutil::TempFile::new("lib-stamp", b"one\n");
+        let mut rsed = Rsed::new();
+        rsed.execute(&format!("e {}", temp.path().display())).unwrap();
+        rsed.execute("1d").unwrap();
+
+        fs::write(temp.path(), b"theirs\n").unwrap();
+        match rsed.execute("w").map_err(|e| e.error) {
+            Err(ErrorType::ChangedOnDisk) => (),
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert_eq!(temp.contents(), b"theirs\n");
+
+        rsed.execute("w!").unwrap();
+        assert_eq!(temp.contents(), b"");
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{
+    BufRead,
+    BufReader,
+    Write
+};
+use std::path::Path;
+
+use cmd::Cmd;
+use ui;
+use ui::Action;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// Recorded sequences of actions, each in a named slot (`a` to `z`).
+///
+/// While recording, every action that was handled successfully is appended
+/// to the slot being recorded, inserted text included. Macros are saved
+/// to files as the input lines they were recorded from, each slot starting
+/// with a line `[x]`.
+#[derive(Debug)]
+pub struct Macros {
+    slots: BTreeMap<char, Vec<Action>>,
+    recording: Option<(char, Vec<Action>)>,
+    playing: Vec<char>
+}
+
+impl Default for Macros {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Macros {
+
+    pub fn new() -> Macros {
+        Macros {
+            slots: BTreeMap::new(),
+            recording: None,
+            playing: Vec::new()
+        }
+    }
+
+    pub fn get(&self, name: char) -> Option<&Vec<Action>> {
+        self.slots.get(&name)
+    }
+
+    pub fn slots(&self) -> &BTreeMap<char, Vec<Action>> {
+        &self.slots
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Starts recording into slot `name`, dropping what it held. Fails if a
+    /// recording is already running, which would be lost.
+    pub fn start_recording(&mut self, name: char) -> Result<()> {
+        if let Some((current, _)) = self.recording {
+            return Err(Error::unknown(&format!("already recording macro '{}'", current)));
+        }
+
+        self.recording = Some((name, Vec::new()));
+        Ok(())
+    }
+
+    /// Stops recording and stores the actions recorded.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recording.take() {
+            Some((name, actions)) => {
+                self.slots.insert(name, actions);
+                Ok(())
+            },
+            None => Err(Error::unknown("not recording"))
+        }
+    }
+
+    /// Adds `action` to the macro being recorded, if any. Commands that
+    /// control recording aren't recorded.
+    pub fn record(&mut self, action: &Action) {
+        if let Action::Command(ref command) = *action {
+            if let Cmd::Record(_) = command.cmd {
+                return;
+            }
+        }
+
+        if let Some((_, ref mut actions)) = self.recording {
+            actions.push(action.clone());
+        }
+    }
+
+    /// Marks slot `name` as being replayed, a macro can't run itself.
+    pub fn start_playing(&mut self, name: char) -> Result<()> {
+        if self.playing.contains(&name) {
+            return Err(Error::unknown(&format!("macro '{}' runs itself", name)));
+        }
+
+        self.playing.push(name);
+        Ok(())
+    }
+
+    pub fn stop_playing(&mut self) {
+        self.playing.pop();
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = File::create(path)?;
+
+        for (name, actions) in &self.slots {
+            writeln!(file, "[{}]", name)?;
+            for action in actions {
+                writeln!(file, "{}", action)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the macros saved in the file at `path`, replacing the slots of
+    /// the same names.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = File::open(path)?;
+        let mut parser = ui::Ui::new();
+        let mut slots = BTreeMap::new();
+        let mut current = None;
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+
+            if !parser.is_insert_mode() && !parser.is_continued() && line.starts_with('[') {
+                let mut chars = line.chars().skip(1);
+                match (chars.next(), chars.next(), chars.next()) {
+                    (Some(c), Some(']'), None) if c.is_ascii_lowercase() => {
+                        current = Some(c);
+                        slots.insert(c, Vec::new());
+                        continue;
+                    },
+                    _ => return Err(Error::detailed(ErrorType::ParseError, format!("invalid macro name '{}'", line)))
+                }
+            }
+
+            let name = match current {
+                Some(name) => name,
+                None => return Err(Error::detailed(ErrorType::ParseError, "macro name expected"))
+            };
+
+            let action = match parser.parse_input(&line)? {
+                Some(action) => action,
+                None => continue
+            };
+            parser.follow(&action);
+
+            slots.entry(name).or_insert_with(Vec::new).push(action);
+        }
+
+        if parser.is_continued() {
+            return Err(Error::detailed(ErrorType::ParseError, "unfinished command at the end of the file"));
+        }
+
+        self.slots.extend(slots);
+        Ok(())
+    }
+
+}
@@ -2,20 +2,62 @@
 extern crate rsed;
 
 use std::env;
+use std::io;
+use std::io::Write;
+use std::process;
 
 fn main() {
-    run(env::args()).unwrap();
+    if let Err(e) = run(env::args()) {
+        let _ = writeln!(io::stderr(), "rsed: {}", e);
+        process::exit(1);
+    }
 }
 
-fn run(mut args: env::Args) -> rsed::Result<()> {
+fn run(args: env::Args) -> rsed::Result<()> {
 
     let mut rsed = rsed::Rsed::new();
+    let terminal = rsed::term::is_stdout_tty();
+    rsed.set_terminal(terminal);
+    rsed.set_color(terminal);
+
+    let args: Vec<String> = args.skip(1).collect();
+
+    // options on the command line win over the configuration files
+    if !args.iter().any(|arg| arg == "--no-config") {
+        if let Err(e) = rsed.load_config_files() {
+            let _ = writeln!(io::stderr(), "rsed: {}", e);
+        }
+    }
+
+    let mut file = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--no-config" => (),
+            "-m" | "--mmap" => rsed.set_map_files(true),
+            "--color" | "--color=always" => rsed.set_color(true),
+            "--color=never" => rsed.set_color(false),
+            "--color=auto" => rsed.set_color(terminal),
+            a if a.starts_with("--color=") => return Err(rsed::Error::unknown("--color must be always, never or auto")),
+            "-E" | "--extended-regexp" => rsed.set_dialect(rsed::dialect::Dialect::Extended),
+            "-c" | "--codec" => {
+                let codec = args.next().ok_or(rsed::Error::unknown("--codec needs an argument"))?;
+                rsed.set_codec(Some(codec.parse()?));
+            },
+            "-e" | "--encoding" => {
+                let label = args.next().ok_or(rsed::Error::unknown("--encoding needs an argument"))?;
+                rsed.set_encoding(Some(rsed::charset::encoding_for_label(&label)?));
+            },
+            _ => file = Some(arg)
+        }
+    }
 
-    if let Some(p) = args.nth(1) {
-        try!(rsed.read_file(p));
+    if let Some(p) = file {
+        rsed.read_file(p)?;
     }
 
-    rsed.main_loop();
+    rsed.main_loop()?;
 
     Ok(())
 }
@@ -0,0 +1,290 @@
+use std::cell::{
+    Cell,
+    RefCell
+};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::str;
+use std::time::SystemTime;
+
+use memmap2::Mmap;
+
+use charset::Charset;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// Number of lines between two entries of the checkpoint index.
+const CHECKPOINT_INTERVAL: usize = 64;
+
+/// A read-only, memory mapped text file.
+///
+/// Opening only counts the lines. The byte offset of every 64th line is
+/// recorded in a checkpoint index as lines are first reached, the offset
+/// of any other line is found by scanning forward from the checkpoint
+/// before it, or from the line looked up last.
+///
+/// Lines are checked to be UTF-8 when they are read. A line that isn't is
+/// cut short at the first invalid byte and the file is marked as damaged,
+/// which `check` reports.
+pub struct MappedFile {
+    map: Mmap,
+    lines: usize,
+    checkpoints: RefCell<Vec<usize>>,
+    /// Index and offset of the line looked up last.
+    last: Cell<(usize, usize)>,
+    damaged: Cell<bool>,
+    /// The mapped file, and its size and modification time when it was
+    /// mapped.
+    file: File,
+    size: u64,
+    modified: Option<SystemTime>
+}
+
+impl fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MappedFile {{ bytes: {}, lines: {} }}", self.map.len(), self.lines)
+    }
+}
+
+impl MappedFile {
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedFile> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+
+        // Safety: rsed only reads the map, and never writes to a file while
+        // it is mapped (see Document::write). Another process truncating the
+        // file makes reads past its new end fault with SIGBUS, which can't
+        // be prevented here. `check` notices a changed file, rsed runs it
+        // before each command, so the window is the command being run.
+        // Rewritten contents can't cause undefined behavior, lines are
+        // validated as they are read.
+        let map = unsafe { Mmap::map(&file) }?;
+
+        let newlines = map.iter().filter(|&&b| b == b'\n').count();
+        let lines = match map.last() {
+            Some(&b'\n') | None => newlines,
+            Some(_) => newlines + 1
+        };
+
+        Ok(MappedFile {
+            map,
+            lines,
+            checkpoints: RefCell::new(vec![0]),
+            last: Cell::new((0, 0)),
+            damaged: Cell::new(false),
+            file,
+            size: metadata.len(),
+            modified: metadata.modified().ok()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines == 0
+    }
+
+    /// Fails if the mapped file changed since it was mapped, or if a line
+    /// read from it wasn't valid UTF-8.
+    ///
+    /// The file is the one that was opened, even if its name now refers
+    /// to another file.
+    pub fn check(&self) -> Result<()> {
+        let metadata = self.file.metadata()?;
+
+        if metadata.len() != self.size || metadata.modified().ok() != self.modified {
+            return Err(Error::detailed(ErrorType::ChangedOnDisk,
+                                       "mapped file changed on disk, ':reload!' reads it again"));
+        }
+
+        if self.damaged.get() {
+            return Err(Error::detailed(ErrorType::InvalidEncoding(Charset::utf8()),
+                                       "mapped file is not valid UTF-8"));
+        }
+
+        Ok(())
+    }
+
+    /// Byte offset of the line after the one starting at `offset`.
+    fn next_line(&self, offset: usize) -> usize {
+        match self.map[offset..].iter().position(|&b| b == b'\n') {
+            Some(n) => offset + n + 1,
+            None => self.map.len()
+        }
+    }
+
+    /// Index and offset of the closest checkpoint at or before line
+    /// `index`, indexing the file up to there if it wasn't yet.
+    fn checkpoint_before(&self, index: usize) -> (usize, usize) {
+        let block = index / CHECKPOINT_INTERVAL;
+        let mut checkpoints = self.checkpoints.borrow_mut();
+
+        while checkpoints.len() <= block {
+            let mut offset = checkpoints[checkpoints.len() - 1];
+            for _ in 0..CHECKPOINT_INTERVAL {
+                offset = self.next_line(offset);
+            }
+            checkpoints.push(offset);
+        }
+
+        (block * CHECKPOINT_INTERVAL, checkpoints[block])
+    }
+
+    /// Byte offset at which line `index` starts.
+    pub fn line_start(&self, index: usize) -> usize {
+        let (last_index, last_offset) = self.last.get();
+
+        // the line looked up last is a better start for reading on
+        let (mut line, mut offset) = if last_index <= index && index - last_index < CHECKPOINT_INTERVAL {
+            (last_index, last_offset)
+        } else {
+            self.checkpoint_before(index)
+        };
+
+        while line < index {
+            offset = self.next_line(offset);
+            line += 1;
+        }
+
+        self.last.set((index, offset));
+        offset
+    }
+
+    /// Returns the line starting at byte `offset` without its line ending,
+    /// along with the offset of the next line.
+    pub fn line_at(&self, offset: usize) -> (&str, usize) {
+        let next = self.next_line(offset);
+
+        let mut line = &self.map[offset..next];
+        if line.ends_with(b"\n") {
+            line = &line[..line.len() - 1];
+        }
+        if line.ends_with(b"\r") {
+            line = &line[..line.len() - 1];
+        }
+
+        match str::from_utf8(line) {
+            Ok(line) => (line, next),
+            Err(e) => {
+                self.damaged.set(true);
+                (str::from_utf8(&line[..e.valid_up_to()]).unwrap_or(""), next)
+            }
+        }
+    }
+
+    /// Whether the first line ends in CR LF.
+    pub fn has_crlf(&self) -> bool {
+        self.map[..self.next_line(0)].ends_with(b"\r\n")
+    }
+
+    pub fn line(&self, index: usize) -> &str {
+        self.line_at(self.line_start(index)).0
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use rope::Rope;
+    use testutil::TempFile;
+
+    fn numbered(count: usize) -> String {
+        (0..count).map(|n| format!("line {}\n", n)).collect()
+    }
+
+    #[test]
+    fn reads_lines() {
+        let temp = TempFile::new("mapped-lines", b"one\r\ntwo\n\nlast");
+        let file = MappedFile::open(temp.path()).unwrap();
+
+        assert_eq!(file.len(), 4);
+        assert_eq!(file.line(0), "one");
+        assert_eq!(file.line(3), "last");
+        assert_eq!(file.line(1), "two");
+        assert_eq!(file.line(2), "");
+        assert_eq!(file.line_at(file.line_start(1)), ("two", 9));
+        assert!(file.check().is_ok());
+    }
+
+    #[test]
+    fn empty_file() {
+        let temp = TempFile::new("mapped-empty", b"");
+        let file = MappedFile::open(temp.path()).unwrap();
+        assert!(file.is_empty());
+    }
+
+    #[test]
+    fn indexes_lazily() {
+        let temp = TempFile::new("mapped-lazy", numbered(10000).as_bytes());
+        let file = MappedFile::open(temp.path()).unwrap();
+        assert_eq!(file.len(), 10000);
+
+        assert_eq!(file.line(10), "line 10");
+        assert_eq!(file.checkpoints.borrow().len(), 1);
+
+        assert_eq!(file.line(5000), "line 5000");
+        assert_eq!(file.checkpoints.borrow().len(), 5000 / CHECKPOINT_INTERVAL + 1);
+
+        // lookups behind the last one go through the index
+        assert_eq!(file.line(4999), "line 4999");
+        assert_eq!(file.line(130), "line 130");
+        assert_eq!(file.line(9999), "line 9999");
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported() {
+        let temp = TempFile::new("mapped-invalid", b"good\nbad \xff byte\n");
+        let file = MappedFile::open(temp.path()).unwrap();
+
+        assert_eq!(file.line(0), "good");
+        assert!(file.check().is_ok());
+
+        assert_eq!(file.line(1), "bad ");
+        match file.check().map_err(|e| e.error) {
+            Err(ErrorType::InvalidEncoding(_)) => (),
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn notices_changes() {
+        let temp = TempFile::new("mapped-changed", b"one\ntwo\n");
+        let file = MappedFile::open(temp.path()).unwrap();
+
+        fs::OpenOptions::new().append(true).open(temp.path()).unwrap().write_all(b"three\n").unwrap();
+        match file.check().map_err(|e| e.error) {
+            Err(ErrorType::ChangedOnDisk) => (),
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn rope_edits_keep_mapped_lines() {
+        let temp = TempFile::new("mapped-rope", numbered(1000).as_bytes());
+        let mut rope = Rope::from_mapped(Rc::new(MappedFile::open(temp.path()).unwrap()));
+
+        rope.remove(10, 990);
+        rope.insert(5, vec!["new".to_string()]);
+
+        let lines: Vec<_> = rope.lines(0, rope.len()).collect();
+        assert_eq!(lines.len(), 21);
+        assert_eq!(lines[4], "line 4");
+        assert_eq!(lines[5], "new");
+        assert_eq!(lines[10], "line 9");
+        assert_eq!(lines[11], "line 990");
+        assert_eq!(rope.get(20), Some("line 999"));
+    }
+}
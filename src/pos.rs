@@ -1,7 +1,9 @@
 use std::str;
+use std::fmt;
 use std::convert;
 use std::ops;
-use regex::Regex;
+use search;
+use search::Direction;
 
 use {
     Result,
@@ -9,39 +11,41 @@ use {
     ErrorType
 };
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Pos {
     Line(usize),
     Current,
-    End
+    End,
+    Mark(char),
+    Search(String, Direction)
 }
 
 pub trait Converter<F, T> {
     fn convert(&self, from: F) -> T;
 }
 
-static POS_RE: &'static str = r"^((?P<current>\.)|(?P<end>\$)|(?P<line>\d+))$";
-static RANGE_RE: &'static str = r"^((?P<all>%{1})|(?P<first>[.$0-9]+)(,(?P<second>[.$0-9]+))?)$";
+static POS_RE: &str = r"^((?P<current>\.)|(?P<end>\$)|(?P<line>\d+))$";
+static RANGE_RE: &str = r"^((?P<all>%{1})|(?P<first>[.$0-9]+)(,(?P<second>[.$0-9]+))?)$";
 
 impl str::FromStr for Pos {
     type Err = Error;
     fn from_str(s: &str) -> Result<Pos> {
-        let re = Regex::new(POS_RE).unwrap();
+        let re = search::grammar_regex(POS_RE);
  
         if let Some(captures) = re.captures(s) {
 
-            if let Some(_) = captures.name("current") {
+            if captures.name("current").is_some() {
                 return Ok(Pos::Current);
             }
 
-            if let Some(_) = captures.name("end") {
+            if captures.name("end").is_some() {
                 return Ok(Pos::End);
             }
 
             if let Some(line) = captures.name("line") {
                 return  match line.parse() {
                     Ok(n) => Ok(Pos::Line(n)),
-                    Err(_) => Err(Error::new(ErrorType::ParseError))
+                    Err(_) => Err(Error::detailed(ErrorType::ParseError, "line number too large"))
                 };
             }
         }
@@ -50,6 +54,45 @@ impl str::FromStr for Pos {
     }
 }
 
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pos::Line(n) => write!(f, "{}", n),
+            Pos::Current => write!(f, "."),
+            Pos::End => write!(f, "$"),
+            Pos::Mark(c) => write!(f, "'{}", c),
+            Pos::Search(ref pattern, Direction::Forward) => write_pattern(f, pattern, '/'),
+            Pos::Search(ref pattern, Direction::Backward) => write_pattern(f, pattern, '?')
+        }
+    }
+}
+
+/// Writes `pattern` between two `delimiter`s, escaping the delimiter
+/// wherever it appears in the pattern.
+///
+/// Patterns as the parser returns them read back the same. In those, a
+/// backslash always escapes the character after it, never the delimiter,
+/// and never ends the pattern.
+pub fn write_pattern(f: &mut fmt::Formatter, pattern: &str, delimiter: char) -> fmt::Result {
+    write!(f, "{}", delimiter)?;
+
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            write!(f, "{}", c)?;
+            if let Some(next) = chars.next() {
+                write!(f, "{}", next)?;
+            }
+        } else if c == delimiter {
+            write!(f, "\\{}", c)?;
+        } else {
+            write!(f, "{}", c)?;
+        }
+    }
+
+    write!(f, "{}", delimiter)
+}
+
 impl convert::From<Range> for Pos {
     fn from(r: Range) -> Pos {
         match r {
@@ -59,7 +102,7 @@ impl convert::From<Range> for Pos {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Range {
     Line(Pos),
     Range(Pos, Pos)
@@ -69,19 +112,19 @@ impl str::FromStr for Range {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Range> {
-        let re = Regex::new(RANGE_RE).unwrap();
+        let re = search::grammar_regex(RANGE_RE);
 
         if let Some(captures) = re.captures(s) {
-            if let Some(_) = captures.name("all") {
+            if captures.name("all").is_some() {
                 return Ok( Range::Range( Pos::Line(1), Pos::End ) );
             }
 
             if let Some(first) = captures.name("first") {
 
-                let first_pos = try!(first.parse::<Pos>());
+                let first_pos = first.parse::<Pos>()?;
 
                 if let Some(second) = captures.name("second") {
-                    let second_pos = try!(second.parse::<Pos>());
+                    let second_pos = second.parse::<Pos>()?;
 
                     return Ok(Range::Range( first_pos, second_pos ));
                 }
@@ -94,29 +137,47 @@ impl str::FromStr for Range {
     }
 }
 
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Range::Line(ref p) => write!(f, "{}", p),
+            Range::Range(ref from, ref to) => write!(f, "{},{}", from, to)
+        }
+    }
+}
+
 impl Range {
 
-    pub fn to_range<'a, C>(&'a self, conv: &C) -> ops::Range<usize> 
-        where C: Converter<&'a Pos, usize> {
+    pub fn to_range<'a, C>(&'a self, conv: &C) -> Result<ops::Range<usize>>
+        where C: Converter<&'a Pos, Result<usize>> {
         match *self {
             Range::Line(ref p) => {
-                let pos = conv.convert(&p);
+                let pos = conv.convert(p)?;
                 if pos == 0 {
-                    ops::Range {
+                    Ok(ops::Range {
                         start: 0,
                         end: 0
-                    }
+                    })
                 } else {
-                    ops::Range { 
+                    Ok(ops::Range { 
                         start: pos - 1, 
                         end: pos
-                    }
+                    })
                 }
             },
 
-            Range::Range(ref f, ref t) => ops::Range {
-                start: conv.convert(&f) - 1, 
-                end: conv.convert(&t)
+            Range::Range(ref f, ref t) => {
+                let start = conv.convert(f)?;
+                let end = conv.convert(t)?;
+
+                if start == 0 || start > end {
+                    return Err(Error::new(ErrorType::InvalidRange(Box::new(self.clone()))));
+                }
+
+                Ok(ops::Range {
+                    start: start - 1, 
+                    end
+                })
             }
         }
     }
@@ -127,3 +188,35 @@ impl Range {
 
 }
 
+
+/// Destination address of a transfer, optionally located in another buffer.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Target {
+    pub buffer: Option<usize>,
+    pub pos: Pos
+}
+
+impl Target {
+    pub fn new(pos: Pos) -> Target {
+        Target {
+            buffer: None,
+            pos
+        }
+    }
+
+    pub fn in_buffer(buffer: usize, pos: Pos) -> Target {
+        Target {
+            buffer: Some(buffer),
+            pos
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.buffer {
+            Some(b) => write!(f, "{}:{}", b, self.pos),
+            None => write!(f, "{}", self.pos)
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use buffer::Buffer;
+
+/// Cut buffers for yank, delete and put.
+///
+/// Every yank or delete fills the unnamed register, naming a register
+/// (`a` to `z`) stores the lines there as well. Naming it in uppercase
+/// (`A` to `Z`) appends the lines to it instead.
+#[derive(Debug)]
+pub struct Registers {
+    unnamed: Option<Buffer>,
+    named: HashMap<char, Buffer>
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registers {
+
+    pub fn new() -> Registers {
+        Registers {
+            unnamed: None,
+            named: HashMap::new()
+        }
+    }
+
+    pub fn get(&self, name: Option<char>) -> Option<&Buffer> {
+        match name {
+            Some(c) => self.named.get(&c.to_ascii_lowercase()),
+            None => self.unnamed.as_ref()
+        }
+    }
+
+    /// Stores `buffer`, the unnamed register ends up with all lines of the
+    /// named one.
+    pub fn set(&mut self, name: Option<char>, buffer: Buffer) {
+        let buffer = match name {
+            Some(c) if c.is_ascii_uppercase() => {
+                let register = self.named.entry(c.to_ascii_lowercase()).or_default();
+                let len = register.len();
+                register.insert_buffer(len, buffer);
+                register.clone()
+            },
+            Some(c) => {
+                self.named.insert(c, buffer.clone());
+                buffer
+            },
+            None => buffer
+        };
+
+        self.unnamed = Some(buffer);
+    }
+
+}
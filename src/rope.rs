@@ -0,0 +1,605 @@
+use std::cmp;
+use std::mem;
+use std::ops;
+use std::rc::Rc;
+use std::slice;
+
+use mapped::MappedFile;
+
+/// Maximum number of lines stored in a single tree node.
+const CHUNK_SIZE: usize = 256;
+
+type Link = Option<Box<Node>>;
+
+/// A run of consecutive lines, either owned or still living in a mapped
+/// file.
+#[derive(Debug, Clone)]
+enum Chunk {
+    Owned(Vec<String>),
+    Mapped(Rc<MappedFile>, ops::Range<usize>)
+}
+
+impl Chunk {
+    fn len(&self) -> usize {
+        match *self {
+            Chunk::Owned(ref lines) => lines.len(),
+            Chunk::Mapped(_, ref range) => range.end - range.start
+        }
+    }
+
+    fn split_off(&mut self, at: usize) -> Chunk {
+        match *self {
+            Chunk::Owned(ref mut lines) => Chunk::Owned(lines.split_off(at)),
+            Chunk::Mapped(ref file, ref mut range) => {
+                let tail = range.start + at .. range.end;
+                range.end = range.start + at;
+                Chunk::Mapped(file.clone(), tail)
+            }
+        }
+    }
+
+    fn get(&self, index: usize) -> &str {
+        match *self {
+            Chunk::Owned(ref lines) => &lines[index],
+            Chunk::Mapped(ref file, ref range) => file.line(range.start + index)
+        }
+    }
+
+    fn lines_from(&self, index: usize) -> ChunkLines<'_> {
+        match *self {
+            Chunk::Owned(ref lines) => ChunkLines::Owned(lines[index..].iter()),
+            Chunk::Mapped(ref file, ref range) => ChunkLines::Mapped {
+                file,
+                offset: file.line_start(range.start + index),
+                remaining: range.end - range.start - index
+            }
+        }
+    }
+
+    /// Joins `self` and the chunk after it, if they continue the same
+    /// mapping or fit into one chunk together. Both are returned otherwise.
+    fn join(self, next: Chunk) -> Result<Chunk, (Chunk, Chunk)> {
+        match (self, next) {
+            (Chunk::Mapped(file, range), Chunk::Mapped(next_file, next_range))
+                if Rc::ptr_eq(&file, &next_file) && range.end == next_range.start => {
+                Ok(Chunk::Mapped(file, range.start..next_range.end))
+            },
+            (chunk, next) => {
+                if chunk.len() + next.len() > CHUNK_SIZE {
+                    return Err((chunk, next));
+                }
+
+                let mut lines = Vec::with_capacity(CHUNK_SIZE);
+                chunk.into_lines(&mut lines);
+                next.into_lines(&mut lines);
+                Ok(Chunk::Owned(lines))
+            }
+        }
+    }
+
+    fn into_lines(self, lines: &mut Vec<String>) {
+        match self {
+            Chunk::Owned(owned) => lines.extend(owned),
+            Chunk::Mapped(file, range) => {
+                let mut offset = file.line_start(range.start);
+                for _ in range {
+                    let (line, next) = file.line_at(offset);
+                    lines.push(line.to_string());
+                    offset = next;
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the lines of a single chunk.
+enum ChunkLines<'a> {
+    Owned(slice::Iter<'a, String>),
+    Mapped {
+        file: &'a MappedFile,
+        offset: usize,
+        remaining: usize
+    }
+}
+
+impl <'a> ChunkLines<'a> {
+    fn len(&self) -> usize {
+        match *self {
+            ChunkLines::Owned(ref iter) => iter.len(),
+            ChunkLines::Mapped { remaining, .. } => remaining
+        }
+    }
+}
+
+impl <'a> Iterator for ChunkLines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        match *self {
+            ChunkLines::Owned(ref mut iter) => iter.next().map(|line| &line[..]),
+            ChunkLines::Mapped { file, ref mut offset, ref mut remaining } => {
+                if *remaining == 0 {
+                    return None;
+                }
+
+                let (line, next) = file.line_at(*offset);
+                *offset = next;
+                *remaining -= 1;
+                Some(line)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    chunk: Chunk,
+    size: usize,
+    priority: u32,
+    left: Link,
+    right: Link
+}
+
+impl Node {
+    fn new(chunk: Chunk, priority: u32) -> Node {
+        Node {
+            size: chunk.len(),
+            chunk,
+            priority,
+            left: None,
+            right: None
+        }
+    }
+
+    fn update(&mut self) {
+        self.size = size(&self.left) + self.chunk.len() + size(&self.right);
+    }
+}
+
+fn size(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+/// Joins two trees, all lines of `left` ending up before those of `right`.
+fn merge(left: Link, right: Link) -> Link {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut l), Some(mut r)) => {
+            if l.priority > r.priority {
+                l.right = merge(l.right.take(), Some(r));
+                l.update();
+                Some(l)
+            } else {
+                r.left = merge(Some(l), r.left.take());
+                r.update();
+                Some(r)
+            }
+        }
+    }
+}
+
+/// Splits a tree into the first `at` lines and the rest, cutting a chunk in
+/// two if `at` falls inside of it.
+fn split(link: Link, at: usize) -> (Link, Link) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (None, None)
+    };
+
+    let left_size = size(&node.left);
+    let chunk_len = node.chunk.len();
+
+    if at <= left_size {
+        let (l, r) = split(node.left.take(), at);
+        node.left = r;
+        node.update();
+        (l, Some(node))
+    } else if at >= left_size + chunk_len {
+        let (l, r) = split(node.right.take(), at - left_size - chunk_len);
+        node.right = l;
+        node.update();
+        (Some(node), r)
+    } else {
+        // the tail keeps the priority, so both halves remain valid heaps
+        let tail = node.chunk.split_off(at - left_size);
+        let mut tail_node = Box::new(Node::new(tail, node.priority));
+        tail_node.right = node.right.take();
+        tail_node.update();
+        node.update();
+        (Some(node), Some(tail_node))
+    }
+}
+
+/// Removes the first chunk of a tree.
+fn take_first(link: &mut Link) -> Option<Chunk> {
+    match *link {
+        None => None,
+        Some(ref mut node) if node.left.is_some() => {
+            let chunk = take_first(&mut node.left);
+            node.update();
+            chunk
+        },
+        Some(_) => {
+            let node = *link.take()?;
+            *link = node.right;
+            Some(node.chunk)
+        }
+    }
+}
+
+/// Removes the last chunk of a tree.
+fn take_last(link: &mut Link) -> Option<Chunk> {
+    match *link {
+        None => None,
+        Some(ref mut node) if node.right.is_some() => {
+            let chunk = take_last(&mut node.right);
+            node.update();
+            chunk
+        },
+        Some(_) => {
+            let node = *link.take()?;
+            *link = node.left;
+            Some(node.chunk)
+        }
+    }
+}
+
+/// Sequence of lines stored as an implicit treap of line chunks.
+///
+/// Lines of a memory mapped file stay in the mapping until the chunk holding
+/// them is edited; only then are the inserted lines stored as owned strings,
+/// while the untouched parts keep pointing into the mapping.
+///
+/// Lookups, inserts and deletes take O(log n) tree operations plus the work
+/// proportional to the number of lines inserted or removed. Small chunks
+/// left on either side of an edit are joined, so that editing line by line
+/// doesn't break the rope up into chunks of single lines.
+#[derive(Debug, Clone)]
+pub struct Rope {
+    root: Link,
+    seed: u32
+}
+
+impl Rope {
+
+    pub fn new() -> Rope {
+        Rope {
+            root: None,
+            seed: 0x9e37_79b9
+        }
+    }
+
+    /// Creates a rope whose lines all still live in `file`.
+    pub fn from_mapped(file: Rc<MappedFile>) -> Rope {
+        let mut rope = Rope::new();
+
+        if !file.is_empty() {
+            let range = 0..file.len();
+            let node = Node::new(Chunk::Mapped(file, range), rope.next_priority());
+            rope.root = Some(Box::new(node));
+        }
+
+        rope
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    fn next_priority(&mut self) -> u32 {
+        // xorshift32, good enough to keep the treap balanced
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+
+    fn build<I: IntoIterator<Item=String>>(&mut self, lines: I) -> (Link, usize) {
+        let mut tree = None;
+        let mut count = 0;
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+
+        for line in lines {
+            chunk.push(line);
+            count += 1;
+
+            if chunk.len() == CHUNK_SIZE {
+                let full = mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE));
+                let node = Node::new(Chunk::Owned(full), self.next_priority());
+                tree = merge(tree, Some(Box::new(node)));
+            }
+        }
+
+        if !chunk.is_empty() {
+            let node = Node::new(Chunk::Owned(chunk), self.next_priority());
+            tree = merge(tree, Some(Box::new(node)));
+        }
+
+        (tree, count)
+    }
+
+    /// Inserts `lines` before the line at index `pos`, returns how many
+    /// lines were inserted.
+    pub fn insert<I: IntoIterator<Item=String>>(&mut self, pos: usize, lines: I) -> usize {
+        let (inserted, count) = self.build(lines);
+
+        if count > 0 {
+            let (before, after) = split(self.root.take(), pos);
+            self.root = merge(merge(before, inserted), after);
+            self.join_at(pos + count);
+            self.join_at(pos);
+        }
+
+        count
+    }
+
+    pub fn push(&mut self, line: String) {
+        let len = self.len();
+        self.insert(len, Some(line));
+    }
+
+    /// Removes the lines with indices in `start..end`.
+    pub fn remove(&mut self, start: usize, end: usize) {
+        let (before, rest) = split(self.root.take(), start);
+        let (_, after) = split(rest, end - start);
+        self.root = merge(before, after);
+        self.join_at(start);
+    }
+
+    /// Joins the chunks ending and starting at line `at`, if they can be.
+    fn join_at(&mut self, at: usize) {
+        if at == 0 || at >= self.len() {
+            return;
+        }
+
+        let (mut before, mut after) = split(self.root.take(), at);
+        let chunks = match (take_last(&mut before), take_first(&mut after)) {
+            (Some(last), Some(first)) => match last.join(first) {
+                Ok(joined) => vec![joined],
+                Err((last, first)) => vec![last, first]
+            },
+            (last, first) => last.into_iter().chain(first).collect()
+        };
+
+        for chunk in chunks {
+            let node = Node::new(chunk, self.next_priority());
+            before = merge(before, Some(Box::new(node)));
+        }
+        self.root = merge(before, after);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        let mut link = &self.root;
+        let mut at = index;
+
+        while let Some(ref node) = *link {
+            let left_size = size(&node.left);
+
+            if at < left_size {
+                link = &node.left;
+            } else if at < left_size + node.chunk.len() {
+                return Some(node.chunk.get(at - left_size));
+            } else {
+                at -= left_size + node.chunk.len();
+                link = &node.right;
+            }
+        }
+
+        None
+    }
+
+    /// Iterates over the lines with indices in `start..end`.
+    pub fn lines(&self, start: usize, end: usize) -> Lines<'_> {
+        let mut lines = Lines {
+            stack: Vec::new(),
+            current: ChunkLines::Owned([].iter()),
+            remaining: end.saturating_sub(start)
+        };
+
+        let mut link = &self.root;
+        let mut at = start;
+
+        while let Some(ref node) = *link {
+            let left_size = size(&node.left);
+
+            if at < left_size {
+                lines.stack.push(node);
+                link = &node.left;
+            } else if at < left_size + node.chunk.len() {
+                lines.current = node.chunk.lines_from(at - left_size);
+                lines.push_left_spine(&node.right);
+                break;
+            } else {
+                at -= left_size + node.chunk.len();
+                link = &node.right;
+            }
+        }
+
+        lines
+    }
+
+    pub fn into_vec(self) -> Vec<String> {
+        let mut lines = Vec::with_capacity(self.len());
+        let mut stack = Vec::new();
+        let mut link = self.root;
+
+        loop {
+            while let Some(mut node) = link {
+                link = node.left.take();
+                stack.push(node);
+            }
+
+            match stack.pop() {
+                Some(mut node) => {
+                    link = node.right.take();
+                    mem::replace(&mut node.chunk, Chunk::Owned(Vec::new())).into_lines(&mut lines);
+                },
+                None => break
+            }
+        }
+
+        lines
+    }
+
+}
+
+/// Iterator over a range of lines of a `Rope`.
+pub struct Lines<'a> {
+    stack: Vec<&'a Node>,
+    current: ChunkLines<'a>,
+    remaining: usize
+}
+
+impl <'a> Lines<'a> {
+    fn push_left_spine(&mut self, mut link: &'a Link) {
+        while let Some(ref node) = *link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl <'a> Iterator for Lines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        loop {
+            if let Some(line) = self.current.next() {
+                self.remaining -= 1;
+                return Some(line);
+            }
+
+            let node = self.stack.pop()?;
+
+            self.current = node.chunk.lines_from(0);
+            self.push_left_spine(&node.right);
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (cmp::min(self.remaining, self.current.len()), Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(prefix: &str, range: ops::Range<usize>) -> Vec<String> {
+        range.map(|n| format!("{} {}", prefix, n)).collect()
+    }
+
+    fn assert_same(rope: &Rope, expected: &[String]) {
+        assert_eq!(rope.len(), expected.len());
+        assert_eq!(rope.lines(0, rope.len()).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn insert_and_remove_across_chunks() {
+        let mut rope = Rope::new();
+        let mut expected = lines("a", 0..1000);
+        rope.insert(0, expected.clone());
+        assert_same(&rope, &expected);
+
+        rope.insert(300, lines("b", 0..10));
+        for (n, line) in lines("b", 0..10).into_iter().enumerate() {
+            expected.insert(300 + n, line);
+        }
+        assert_same(&rope, &expected);
+
+        rope.remove(250, 700);
+        expected.drain(250..700);
+        assert_same(&rope, &expected);
+
+        rope.push("last".to_string());
+        expected.push("last".to_string());
+        assert_same(&rope, &expected);
+    }
+
+    #[test]
+    fn matches_a_vec_under_random_edits() {
+        let mut rope = Rope::new();
+        let mut expected: Vec<String> = Vec::new();
+        let mut seed = 12345u32;
+        let mut random = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % n
+        };
+
+        for round in 0..500 {
+            let pos = random(expected.len() + 1);
+            if random(3) == 0 && !expected.is_empty() {
+                let end = cmp::min(pos + random(600), expected.len());
+                rope.remove(pos, end);
+                expected.drain(pos..end);
+            } else {
+                let added = lines(&round.to_string(), 0..random(700));
+                rope.insert(pos, added.clone());
+                for (n, line) in added.into_iter().enumerate() {
+                    expected.insert(pos + n, line);
+                }
+            }
+
+            if !expected.is_empty() {
+                let index = random(expected.len());
+                assert_eq!(rope.get(index), Some(&expected[index][..]));
+
+                let end = cmp::min(index + random(1000), expected.len());
+                assert_eq!(rope.lines(index, end).collect::<Vec<_>>(), &expected[index..end]);
+            }
+        }
+
+        assert_same(&rope, &expected);
+        assert_eq!(rope.get(expected.len()), None);
+        assert_eq!(rope.into_vec(), expected);
+    }
+
+    fn chunks(link: &Link) -> usize {
+        link.as_ref().map_or(0, |node| chunks(&node.left) + 1 + chunks(&node.right))
+    }
+
+    #[test]
+    fn joins_small_chunks() {
+        let mut rope = Rope::new();
+        let mut expected = Vec::new();
+        for n in 0..2000 {
+            let pos = n * 7 % (expected.len() + 1);
+            rope.insert(pos, Some(n.to_string()));
+            expected.insert(pos, n.to_string());
+        }
+        assert_same(&rope, &expected);
+        assert!(chunks(&rope.root) <= 2 * rope.len() / CHUNK_SIZE + 1);
+
+        for n in 0..1500 {
+            let pos = n * 13 % expected.len();
+            rope.remove(pos, pos + 1);
+            expected.remove(pos);
+        }
+        assert_same(&rope, &expected);
+        assert!(chunks(&rope.root) <= 2 * rope.len() / CHUNK_SIZE + 1);
+    }
+
+    #[test]
+    fn empty_rope() {
+        let mut rope = Rope::new();
+        assert!(rope.is_empty());
+        assert_eq!(rope.get(0), None);
+        assert_eq!(rope.lines(0, 0).next(), None);
+
+        rope.insert(0, Vec::new());
+        assert!(rope.is_empty());
+    }
+}
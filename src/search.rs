@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::ops;
+use std::rc::Rc;
+
+use regex::{
+    Captures,
+    Regex
+};
+
+use buffer::Buffer;
+use dialect;
+use dialect::Dialect;
+use signal;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// Compiled user patterns kept before the cache is cleared.
+const MAX_CACHED_PATTERNS: usize = 64;
+
+thread_local! {
+    static GRAMMAR: RefCell<HashMap<&'static str, Rc<Regex>>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the compiled form of one of rsed's own grammar regexes, each is
+/// only compiled once per thread.
+pub fn grammar_regex(pattern: &'static str) -> Rc<Regex> {
+    GRAMMAR.with(|grammar| {
+        grammar.borrow_mut()
+            .entry(pattern)
+            .or_insert_with(|| Rc::new(Regex::new(pattern).unwrap()))
+            .clone()
+    })
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    Forward,
+    Backward
+}
+
+/// Searches buffers for user patterns.
+///
+/// Compiled patterns are cached and the last pattern used is remembered,
+/// so that an empty pattern repeats the previous search. Patterns are
+/// written in a POSIX dialect and translated before being compiled.
+#[derive(Debug)]
+pub struct Search {
+    patterns: RefCell<HashMap<String, Rc<Regex>>>,
+    last: RefCell<Option<String>>,
+    dialect: Dialect
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Search {
+
+    pub fn new() -> Search {
+        Search {
+            patterns: RefCell::new(HashMap::new()),
+            last: RefCell::new(None),
+            dialect: Dialect::Basic
+        }
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        if dialect != self.dialect {
+            self.patterns.borrow_mut().clear();
+            self.dialect = dialect;
+        }
+    }
+
+    pub fn last_pattern(&self) -> Option<String> {
+        self.last.borrow().clone()
+    }
+
+    /// Compiles `pattern`, or the last pattern if it is empty, and makes it
+    /// the last pattern.
+    pub fn compile(&self, pattern: &str) -> Result<Rc<Regex>> {
+        let pattern = if pattern.is_empty() {
+            match *self.last.borrow() {
+                Some(ref last) => last.clone(),
+                None => return Err(Error::new(ErrorType::NoPreviousPattern))
+            }
+        } else {
+            pattern.to_string()
+        };
+
+        if let Some(regex) = self.patterns.borrow().get(&pattern) {
+            *self.last.borrow_mut() = Some(pattern);
+            return Ok(regex.clone());
+        }
+
+        let regex = Rc::new(Regex::new(&dialect::translate(&pattern, self.dialect)?)?);
+
+        let mut patterns = self.patterns.borrow_mut();
+        if patterns.len() >= MAX_CACHED_PATTERNS {
+            patterns.clear();
+        }
+        patterns.insert(pattern.clone(), regex.clone());
+        *self.last.borrow_mut() = Some(pattern);
+
+        Ok(regex)
+    }
+
+    /// Finds the next line after `line` (or the previous one before it)
+    /// matching `pattern`, wrapping around the end of the buffer. Lines are
+    /// numbered from 1, the search may end on `line` itself.
+    pub fn find_line(&self, buffer: &Buffer, line: usize, pattern: &str, direction: Direction) -> Result<usize> {
+        let regex = self.compile(pattern)?;
+        let len = buffer.len();
+
+        // the part after the start, then the part before it, each read in
+        // order rather than line by line
+        let ranges = match direction {
+            Direction::Forward => {
+                let start = if line >= len { 0 } else { line };
+                [start..len, 0..start]
+            },
+            Direction::Backward => {
+                let start = if line <= 1 { len } else { line - 1 };
+                [0..start, start..len]
+            }
+        };
+
+        for range in ranges {
+            let found = match direction {
+                Direction::Forward => find_forward(buffer, range, &regex)?,
+                Direction::Backward => find_backward(buffer, range, &regex)?
+            };
+            if let Some(index) = found {
+                return Ok(index + 1);
+            }
+        }
+
+        Err(Error::new(ErrorType::NoMatch))
+    }
+
+}
+
+/// Index of the first line in `range` matching `regex`.
+fn find_forward(buffer: &Buffer, range: ops::Range<usize>, regex: &Regex) -> Result<Option<usize>> {
+    for (n, text) in buffer.lines(&range).enumerate() {
+        if n % signal::CHECK_INTERVAL == 0 {
+            signal::check_interrupt()?;
+        }
+
+        if regex.is_match(text) {
+            return Ok(Some(range.start + n));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Index of the last line in `range` matching `regex`. Lines are only read
+/// forward, so the range is searched in blocks from its end.
+fn find_backward(buffer: &Buffer, range: ops::Range<usize>, regex: &Regex) -> Result<Option<usize>> {
+    let mut end = range.end;
+
+    while end > range.start {
+        signal::check_interrupt()?;
+
+        let start = cmp::max(range.start, end.saturating_sub(signal::CHECK_INTERVAL));
+        let block: Vec<&str> = buffer.lines(&(start..end)).collect();
+        if let Some(n) = block.iter().rposition(|text| regex.is_match(text)) {
+            return Ok(Some(start + n));
+        }
+
+        end = start;
+    }
+
+    Ok(None)
+}
+
+/// Replaces the `nth` match of `regex` in `line`, or every match from the
+/// `nth` on if `global`, the way `s` does. Returns None if there is no such
+/// match.
+pub fn substitute(regex: &Regex, line: &str, replacement: &str, global: bool, nth: usize) -> Option<String> {
+    let mut result = String::new();
+    let mut end = 0;
+    let mut replaced = false;
+
+    for (n, captures) in regex.captures_iter(line).enumerate() {
+        if n + 1 < nth {
+            continue;
+        }
+
+        let (start, match_end) = captures.pos(0).unwrap();
+        result.push_str(&line[end..start]);
+        expand(replacement, &captures, &mut result);
+        end = match_end;
+        replaced = true;
+
+        if !global {
+            break;
+        }
+    }
+
+    if !replaced {
+        return None;
+    }
+
+    result.push_str(&line[end..]);
+    Some(result)
+}
+
+/// Appends `replacement` to `out` with `&` replaced by the match and `\1`
+/// to `\9` by its groups. A backslash takes any other character literally.
+fn expand(replacement: &str, captures: &Captures, out: &mut String) {
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str(captures.at(0).unwrap_or("")),
+            '\\' => match chars.next() {
+                Some(d @ '1'..='9') => {
+                    let group = d as usize - '0' as usize;
+                    out.push_str(captures.at(group).unwrap_or(""));
+                },
+                Some(next) => out.push(next),
+                None => out.push(c)
+            },
+            c => out.push(c)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(count: usize) -> Buffer {
+        (1..count + 1).map(|n| format!("line {}", n)).collect()
+    }
+
+    #[test]
+    fn finds_lines_wrapping_around() {
+        let search = Search::new();
+        let buffer = numbered(10000);
+
+        assert_eq!(search.find_line(&buffer, 1, "line 9999$", Direction::Forward).unwrap(), 9999);
+        assert_eq!(search.find_line(&buffer, 9999, "line 2$", Direction::Forward).unwrap(), 2);
+        assert_eq!(search.find_line(&buffer, 10000, "line 1$", Direction::Forward).unwrap(), 1);
+        assert_eq!(search.find_line(&buffer, 20, "line 20$", Direction::Forward).unwrap(), 20);
+
+        assert_eq!(search.find_line(&buffer, 9000, "line 2$", Direction::Backward).unwrap(), 2);
+        assert_eq!(search.find_line(&buffer, 2, "line 5000$", Direction::Backward).unwrap(), 5000);
+        assert_eq!(search.find_line(&buffer, 1, "line 10000$", Direction::Backward).unwrap(), 10000);
+        // the closest match before the line, across blocks
+        assert_eq!(search.find_line(&buffer, 9000, "0$", Direction::Backward).unwrap(), 8990);
+        assert_eq!(search.find_line(&buffer, 5, "line 999.$", Direction::Backward).unwrap(), 9999);
+    }
+
+    #[test]
+    fn reports_no_match() {
+        let search = Search::new();
+
+        match search.find_line(&numbered(100), 1, "none", Direction::Backward).map_err(|e| e.error) {
+            Err(ErrorType::NoMatch) => (),
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(search.find_line(&Buffer::new(), 0, "x", Direction::Forward).is_err());
+        assert_eq!(search.last_pattern(), Some("x".to_string()));
+    }
+
+    #[test]
+    fn substitutes_matches() {
+        let regex = Regex::new("o(.)").unwrap();
+
+        assert_eq!(substitute(&regex, "foo bor", "[&]", false, 1), Some("f[oo] bor".to_string()));
+        assert_eq!(substitute(&regex, "foo bor", "\\1\\&", true, 1), Some("fo& br&".to_string()));
+        assert_eq!(substitute(&regex, "foo bor", "x", false, 2), Some("foo bx".to_string()));
+        assert_eq!(substitute(&regex, "foo bor", "x", false, 3), None);
+        assert_eq!(substitute(&regex, "fax", "x", true, 1), None);
+    }
+}
@@ -0,0 +1,141 @@
+use std::io;
+use std::io::Read;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::ptr;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering
+};
+
+#[cfg(unix)]
+use libc;
+
+use {
+    Result,
+    Error,
+    ErrorType
+};
+
+/// Set by the signal handler when the terminal hangs up.
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+/// Set by the signal handler on Ctrl-C, until the main loop takes notice.
+#[cfg(not(test))]
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+// tests running side by side each get their own flag, set by `raise`
+#[cfg(test)]
+thread_local!(static INTERRUPT: AtomicBool = const { AtomicBool::new(false) });
+
+#[cfg(not(test))]
+fn with_interrupt<T, F: FnOnce(&AtomicBool) -> T>(f: F) -> T {
+    f(&INTERRUPT)
+}
+
+#[cfg(test)]
+fn with_interrupt<T, F: FnOnce(&AtomicBool) -> T>(f: F) -> T {
+    INTERRUPT.with(f)
+}
+
+/// Acts as if Ctrl-C was pressed, on the current thread only.
+#[cfg(test)]
+pub fn raise() {
+    with_interrupt(|interrupt| interrupt.store(true, Ordering::SeqCst));
+}
+
+/// Lines worked through between two checks for an interrupt in loops over
+/// a buffer.
+pub const CHECK_INTERVAL: usize = 4096;
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    // only async-signal-safe operations in here
+    if signal == libc::SIGHUP {
+        HANGUP.store(true, Ordering::SeqCst);
+    } else {
+        with_interrupt(|interrupt| interrupt.store(true, Ordering::SeqCst));
+    }
+}
+
+/// Installs the handlers for SIGHUP and SIGINT. They only set a flag, rsed
+/// acts on it once it is back in its main loop.
+///
+/// The handlers are installed without `SA_RESTART`, so that a read waiting
+/// for input is interrupted.
+#[cfg(unix)]
+pub fn install() {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGHUP, &action, ptr::null_mut());
+        libc::sigaction(libc::SIGINT, &action, ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {
+}
+
+/// Whether a hangup was received.
+pub fn hangup_received() -> bool {
+    HANGUP.load(Ordering::SeqCst)
+}
+
+/// Whether an interrupt was received and not taken yet.
+pub fn interrupt_pending() -> bool {
+    with_interrupt(|interrupt| interrupt.load(Ordering::SeqCst))
+}
+
+/// Returns whether an interrupt was received, and forgets about it.
+pub fn take_interrupt() -> bool {
+    with_interrupt(|interrupt| interrupt.swap(false, Ordering::SeqCst))
+}
+
+/// Fails if an interrupt is pending, so that long running operations can
+/// give up with `signal::check_interrupt()?`.
+pub fn check_interrupt() -> Result<()> {
+    if interrupt_pending() {
+        Err(Error::new(ErrorType::Interrupted))
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether reading should stop because of a signal.
+pub fn input_cancelled() -> bool {
+    hangup_received() || interrupt_pending()
+}
+
+/// Reader that gives up with an error when a signal rsed handles arrived,
+/// instead of retrying like `BufRead::read_line` does.
+///
+/// Wrapped around a file, it also makes reading the file cancellable.
+pub struct Interruptible<R> {
+    inner: R
+}
+
+impl <R: Read> Interruptible<R> {
+    pub fn new(inner: R) -> Interruptible<R> {
+        Interruptible {
+            inner
+        }
+    }
+}
+
+impl <R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if input_cancelled() {
+            return Err(io::Error::other("interrupted"));
+        }
+
+        match self.inner.read(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted && input_cancelled() => {
+                Err(io::Error::other("interrupted"))
+            },
+            result => result
+        }
+    }
+}
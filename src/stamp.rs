@@ -0,0 +1,161 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io;
+use std::io::{
+    Read,
+    Write
+};
+use std::path::Path;
+use std::time::SystemTime;
+
+use Result;
+
+/// What a file on disk looked like when it was read or written, to notice
+/// when someone else changes it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    size: u64,
+    hash: Option<u64>
+}
+
+impl FileStamp {
+
+    /// Stamps the file at `path` with the hash of its contents, as
+    /// computed by a `Hashing` reader or writer that saw all of them.
+    pub fn new(path: &Path, hash: Option<u64>) -> Result<FileStamp> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(FileStamp {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            hash
+        })
+    }
+
+    /// Whether the file at `path` is different from when it was stamped.
+    ///
+    /// A file that was only touched isn't, if its hash is known. A file
+    /// that doesn't exist anymore isn't either, writing it clobbers nothing.
+    pub fn is_changed(&self, path: &Path) -> Result<bool> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into())
+        };
+
+        if metadata.len() != self.size {
+            return Ok(true);
+        }
+
+        if metadata.modified().ok() == self.modified {
+            return Ok(false);
+        }
+
+        match self.hash {
+            Some(hash) => {
+                let mut reader = Hashing::new(File::open(path)?);
+                io::copy(&mut reader, &mut io::sink())?;
+                Ok(reader.hash() != hash)
+            },
+            None => Ok(true)
+        }
+    }
+
+}
+
+/// Reader or writer that hashes the bytes passing through it.
+pub struct Hashing<T> {
+    inner: T,
+    hasher: DefaultHasher
+}
+
+impl <T> Hashing<T> {
+
+    pub fn new(inner: T) -> Hashing<T> {
+        Hashing {
+            inner,
+            hasher: DefaultHasher::new()
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+}
+
+impl <R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl <W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use testutil::TempFile;
+
+    fn hashed_stamp(file: &TempFile) -> FileStamp {
+        let mut reader = Hashing::new(File::open(file.path()).unwrap());
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        FileStamp::new(file.path(), Some(reader.hash())).unwrap()
+    }
+
+    fn touch(path: &Path) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+    }
+
+    #[test]
+    fn touching_changes_nothing_known() {
+        let file = TempFile::new("stamp-touched", b"text\n");
+        let stamp = hashed_stamp(&file);
+        let unhashed = FileStamp::new(file.path(), None).unwrap();
+        assert!(!stamp.is_changed(file.path()).unwrap());
+        assert!(!unhashed.is_changed(file.path()).unwrap());
+
+        touch(file.path());
+        assert!(!stamp.is_changed(file.path()).unwrap());
+        // without the hash only the metadata can tell
+        assert!(unhashed.is_changed(file.path()).unwrap());
+    }
+
+    #[test]
+    fn rewriting_is_a_change() {
+        let file = TempFile::new("stamp-rewritten", b"text\n");
+        let stamp = hashed_stamp(&file);
+
+        fs::write(file.path(), b"next\n").unwrap();
+        touch(file.path());
+        assert!(stamp.is_changed(file.path()).unwrap());
+
+        fs::write(file.path(), b"longer text\n").unwrap();
+        assert!(stamp.is_changed(file.path()).unwrap());
+
+        // nothing is left to clobber
+        fs::remove_file(file.path()).unwrap();
+        assert!(!stamp.is_changed(file.path()).unwrap());
+    }
+}
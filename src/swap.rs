@@ -0,0 +1,104 @@
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::time::{
+    Duration,
+    Instant
+};
+
+use buffer::Buffer;
+
+use Result;
+
+/// Time between two writes of a swap file while the buffer keeps changing.
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+
+/// `.name.rsed-swp` next to the file at `path`.
+pub fn swap_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or(path.as_os_str()));
+    name.push(".rsed-swp");
+    path.with_file_name(name)
+}
+
+/// Reads the text saved in the swap file at `path`.
+pub fn recover(path: &Path) -> Result<Buffer> {
+    Buffer::from_buf_read(io::BufReader::new(File::open(path)?))
+}
+
+/// Snapshot of a buffer kept next to the file it was read from, so that
+/// edits survive rsed being killed.
+///
+/// The snapshot is written when the buffer changed, at most every few
+/// seconds unless it is flushed. Dropping the swap leaves the file behind,
+/// it has to be removed once the buffer was written or discarded.
+#[derive(Debug)]
+pub struct Swap {
+    path: PathBuf,
+    revision: usize,
+    written: Option<Instant>
+}
+
+impl Swap {
+
+    /// Creates the swap of the file at `file`, nothing is written yet.
+    pub fn new(file: &Path) -> Swap {
+        Swap {
+            path: swap_path(file),
+            revision: 0,
+            written: None
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saves `buffer`, which is at `revision`, unless it was saved at that
+    /// revision already or the last save is too recent.
+    pub fn update(&mut self, buffer: &Buffer, revision: usize) -> Result<()> {
+        if self.written.is_some_and(|written| written.elapsed() < SWAP_INTERVAL) {
+            return Ok(());
+        }
+
+        self.flush(buffer, revision)
+    }
+
+    /// Saves `buffer`, which is at `revision`, unless it was saved at that
+    /// revision already.
+    pub fn flush(&mut self, buffer: &Buffer, revision: usize) -> Result<()> {
+        if revision == self.revision {
+            return Ok(());
+        }
+
+        // write a temporary file first, so that a crash while writing
+        // doesn't destroy the previous snapshot
+        let mut temp_name = self.path.as_os_str().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+
+        let mut file = io::BufWriter::new(File::create(&temp_path)?);
+        buffer.write(&mut file)?;
+        file.flush()?;
+        fs::rename(&temp_path, &self.path)?;
+
+        self.revision = revision;
+        self.written = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Removes the swap file, once the buffer was written or discarded.
+    pub fn remove(&mut self) {
+        if self.written.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+        self.revision = 0;
+    }
+
+}
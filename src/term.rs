@@ -0,0 +1,299 @@
+use std::cmp;
+use std::env;
+use std::io;
+use std::io::Read;
+use std::str;
+#[cfg(unix)]
+use std::mem;
+
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use signal;
+
+use Result;
+#[cfg(not(unix))]
+use Error;
+
+/// Terminal height assumed when it can't be determined.
+const DEFAULT_ROWS: usize = 24;
+
+/// Returns the number of rows and columns of the terminal standard output
+/// is connected to, or `None` if it isn't a terminal.
+#[cfg(unix)]
+pub fn size() -> Option<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_row > 0 && size.ws_col > 0 {
+        Some((size.ws_row as usize, size.ws_col as usize))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn size() -> Option<(usize, usize)> {
+    None
+}
+
+pub fn rows() -> Option<usize> {
+    size().map(|(rows, _)| rows)
+}
+
+/// Whether standard input is a terminal.
+#[cfg(unix)]
+pub fn is_stdin_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+pub fn is_stdin_tty() -> bool {
+    false
+}
+
+/// Whether standard output is a terminal.
+#[cfg(unix)]
+pub fn is_stdout_tty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+pub fn is_stdout_tty() -> bool {
+    false
+}
+
+/// Whether both standard input and output are terminals, which the line
+/// editor and visual mode need as they draw on standard output.
+pub fn is_interactive() -> bool {
+    is_stdin_tty() && is_stdout_tty()
+}
+
+/// Number of lines `z` prints by default: a screenful, less the lines taken
+/// by the command and the next prompt. Falls back to `$LINES` when output
+/// isn't a terminal.
+pub fn window_size() -> usize {
+    let rows = rows()
+        .or_else(|| env::var("LINES").ok().and_then(|lines| lines.parse().ok()))
+        .unwrap_or(DEFAULT_ROWS);
+
+    cmp::max(rows, 3) - 2
+}
+
+/// Keeps the terminal on standard input in raw mode, where every key press
+/// is read right away and nothing is echoed, until it is dropped.
+///
+/// Signals are still generated for Ctrl-C and friends.
+#[cfg(unix)]
+pub struct RawMode {
+    original: libc::termios
+}
+
+#[cfg(unix)]
+impl RawMode {
+    pub fn enable() -> Result<RawMode> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(From::from(io::Error::last_os_error()));
+        }
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::IEXTEN);
+        raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(From::from(io::Error::last_os_error()));
+        }
+
+        Ok(RawMode {
+            original
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+    }
+}
+
+#[cfg(not(unix))]
+pub struct RawMode;
+
+#[cfg(not(unix))]
+impl RawMode {
+    pub fn enable() -> Result<RawMode> {
+        Err(Error::unknown("raw terminal mode is not supported"))
+    }
+}
+
+/// Unbuffered reader of standard input, so that `read_key` can tell
+/// whether the rest of an escape sequence is already waiting.
+pub struct TerminalInput;
+
+#[cfg(unix)]
+impl Read for TerminalInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n >= 0 {
+                return Ok(n as usize);
+            }
+
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+            if signal::input_cancelled() {
+                return Err(io::Error::other("interrupted"));
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Read for TerminalInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdin().read(buf)
+    }
+}
+
+/// Whether input arrives on standard input within `timeout` milliseconds.
+#[cfg(unix)]
+pub fn input_pending(timeout: i32) -> bool {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0
+    };
+
+    unsafe { libc::poll(&mut fd, 1, timeout) > 0 }
+}
+
+#[cfg(not(unix))]
+pub fn input_pending(_timeout: i32) -> bool {
+    true
+}
+
+/// Input that `read_key` reads key presses from.
+pub trait KeyInput: Read {
+    /// Whether more input arrives within `timeout` milliseconds.
+    fn pending(&mut self, timeout: i32) -> bool;
+}
+
+impl KeyInput for TerminalInput {
+    fn pending(&mut self, timeout: i32) -> bool {
+        input_pending(timeout)
+    }
+}
+
+/// How long to wait for the rest of an escape sequence before taking the
+/// escape key on its own, in milliseconds.
+const ESCAPE_TIMEOUT: i32 = 50;
+
+/// A key press read from a terminal in raw mode.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Enter,
+    Tab,
+    Escape,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Unknown
+}
+
+fn read_byte<R: Read>(input: &mut R) -> Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0]))
+    }
+}
+
+/// Reads a key press, `None` at the end of input.
+pub fn read_key<R: KeyInput>(input: &mut R) -> Result<Option<Key>> {
+    let byte = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None)
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        8 | 127 => Key::Backspace,
+        27 if !input.pending(ESCAPE_TIMEOUT) => Key::Escape,
+        27 => read_escape(input)?,
+        1..=26 => Key::Ctrl((b'a' + byte - 1) as char),
+        0..=127 => Key::Char(byte as char),
+        _ => {
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4
+            };
+
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte(input)? {
+                    Some(b) => bytes.push(b),
+                    None => return Ok(None)
+                }
+            }
+
+            match str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown
+            }
+        }
+    };
+
+    Ok(Some(key))
+}
+
+/// Reads the rest of an escape sequence sent for a cursor or editing key.
+fn read_escape<R: Read>(input: &mut R) -> Result<Key> {
+    match read_byte(input)? {
+        Some(b'[') | Some(b'O') => (),
+        _ => return Ok(Key::Unknown)
+    }
+
+    let key = match read_byte(input)? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(digit @ b'0'..=b'9') => {
+            let mut code = vec![digit];
+            loop {
+                match read_byte(input)? {
+                    Some(b'~') => break,
+                    Some(b) if b.is_ascii_digit() || b == b';' => code.push(b),
+                    _ => return Ok(Key::Unknown)
+                }
+            }
+
+            match &code[..] {
+                b"1" | b"7" => Key::Home,
+                b"4" | b"8" => Key::End,
+                b"3" => Key::Delete,
+                _ => Key::Unknown
+            }
+        },
+        _ => Key::Unknown
+    };
+
+    Ok(key)
+}
@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::process;
+
+/// A file in the temporary directory, removed when dropped.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    /// Creates the file `name`, made unique to this process, holding
+    /// `contents`.
+    pub fn new(name: &str, contents: &[u8]) -> TempFile {
+        let path = env::temp_dir().join(format!("rsed-{}-{}", name, process::id()));
+        fs::write(&path, contents).unwrap();
+        TempFile(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        fs::read(&self.0).unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
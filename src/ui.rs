@@ -1,11 +1,29 @@
 
-use std::io;
+use std::cell::RefCell;
+use std::cmp;
+use std::fmt;
+use std::io::{
+    BufRead,
+    Write
+};
 use std::ops;
+use std::vec::IntoIter;
 
-use Result;
+use {
+    Result,
+    Error,
+    ErrorType
+};
 
 use buffer::Buffer;
 use cmd;
+use highlight;
+use highlight::{
+    Highlighter,
+    StateCache,
+    Style,
+    Token
+};
 
 #[derive(Debug)]
 pub enum Mode {
@@ -15,85 +33,501 @@ pub enum Mode {
 
 #[derive(Debug)]
 pub struct Ui {
-    mode: Mode
+    mode: Mode,
+    output: Output,
+    color: bool,
+    prompt: String,
+    verbose: bool,
+    /// Lines of a command that continues on the next one.
+    pending: Option<String>
 }
 
+// ANSI escape codes used when colours are enabled
+const DIM: &str = "\x1b[2m";
+const HIGHLIGHT: &str = "\x1b[1;31m";
+const CONTROL: &str = "\x1b[7m";
+const ERROR: &str = "\x1b[31m";
+const DIFF_HEADER: &str = "\x1b[1m";
+const DIFF_HUNK: &str = "\x1b[36m";
+const DIFF_DELETED: &str = "\x1b[31m";
+const DIFF_INSERTED: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+fn syntax_color(style: Style) -> &'static str {
+    match style {
+        Style::Comment => "\x1b[90m",
+        Style::String => "\x1b[32m",
+        Style::Keyword => "\x1b[35m",
+        Style::Number => "\x1b[36m",
+        Style::Key => "\x1b[34m",
+        Style::Variable => "\x1b[33m"
+    }
+}
+
+/// Lines printed while handling a single input line.
 #[derive(Debug)]
+pub struct Output {
+    lines: Vec<String>
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Output {
+    pub fn new() -> Output {
+        Output {
+            lines: Vec::new()
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+impl IntoIterator for Output {
+    type Item = String;
+    type IntoIter = IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lines.into_iter()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Action {
-    Command(cmd::Cmd),
+    Command(cmd::Command),
     Insert(String),
     InsertEnd,
 }
 
-#[derive(Debug)]
+/// Writes an action as the input line it was parsed from, escaping inserted
+/// text that would otherwise end the input.
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::Command(ref command) => write!(f, "{}", command),
+            Action::Insert(ref line) if line == "." || is_escaped_dot(line) => write!(f, "\\{}", line),
+            Action::Insert(ref line) => write!(f, "{}", line),
+            Action::InsertEnd => write!(f, ".")
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum PrintOption {
     Normal,
     Numbered,
     LineEndings
 }
 
+/// Consecutive lines to display, along with the byte ranges to highlight in
+/// some of them.
+#[derive(Debug)]
+pub struct Section {
+    range: ops::Range<usize>,
+    highlights: Vec<(usize, Vec<ops::Range<usize>>)>
+}
+
+impl Section {
+    pub fn new(range: ops::Range<usize>) -> Section {
+        Section {
+            range,
+            highlights: Vec::new()
+        }
+    }
+
+    pub fn range(&self) -> &ops::Range<usize> {
+        &self.range
+    }
+
+    /// Grows the section so that it ends at `end`.
+    pub fn extend_to(&mut self, end: usize) {
+        self.range.end = cmp::max(self.range.end, end);
+    }
+
+    /// Highlights `spans` of the line at `index`, lines must be added in
+    /// order.
+    pub fn highlight(&mut self, index: usize, spans: Vec<ops::Range<usize>>) {
+        self.highlights.push((index, spans));
+    }
+}
+
 pub struct DisplayModel<'a> {
     buffer: &'a Buffer,
-    range: ops::Range<usize>,
-    option: PrintOption
+    sections: Vec<Section>,
+    option: PrintOption,
+    highlighter: Option<(&'a dyn Highlighter, &'a RefCell<StateCache>)>
 }
 
 impl <'a> DisplayModel<'a> {
-    pub fn new(buffer: &'a Buffer, range: ops::Range<usize>, option: PrintOption) -> DisplayModel {
+    pub fn new(buffer: &'a Buffer, range: ops::Range<usize>, option: PrintOption) -> DisplayModel<'a> {
+        DisplayModel::with_sections(buffer, vec![Section::new(range)], option)
+    }
+
+    /// Displays several sections, separated by a `--` line.
+    pub fn with_sections(buffer: &'a Buffer, sections: Vec<Section>, option: PrintOption) -> DisplayModel<'a> {
         DisplayModel {
-            buffer: buffer,
-            range: range,
-            option: option
+            buffer,
+            sections,
+            option,
+            highlighter: None
         }
     }
+
+    /// Colours the syntax of the lines with `highlighter`, only used when
+    /// colours are enabled. The states lines start in are looked up in,
+    /// and added to, `states`.
+    pub fn set_highlighter(&mut self, highlighter: Option<&'a dyn Highlighter>, states: &'a RefCell<StateCache>) {
+        self.highlighter = highlighter.map(|highlighter| (highlighter, states));
+    }
 }
 
+/// Shows `c` the way `l` does if it is a control character or a backslash.
+fn escape_char(c: char) -> Option<String> {
+    match c {
+        '\\' => Some("\\\\".to_string()),
+        '\x07' => Some("\\a".to_string()),
+        '\x08' => Some("\\b".to_string()),
+        '\x0c' => Some("\\f".to_string()),
+        '\r' => Some("\\r".to_string()),
+        '\t' => Some("\\t".to_string()),
+        '\x0b' => Some("\\v".to_string()),
+        c if c.is_control() => {
+            let mut bytes = [0; 4];
+            Some(c.encode_utf8(&mut bytes).bytes().map(|b| format!("\\{:03o}", b)).collect())
+        },
+        _ => None
+    }
+}
+
+/// Builds the line marking `spans` of `line` with `^`, keeping tabs so that
+/// the marks line up with the text above them.
+fn marker_line(prefix: &str, line: &str, spans: &[ops::Range<usize>]) -> String {
+    let marked = |offset: usize| spans.iter().any(|span| {
+        (span.start <= offset && offset < span.end) || (span.start == offset && span.end == offset)
+    });
+
+    let mut marker = prefix.to_string();
+
+    for (offset, c) in line.char_indices() {
+        marker.push(match c {
+            _ if marked(offset) => '^',
+            '\t' => '\t',
+            _ => ' '
+        });
+    }
+
+    // an empty match at the end of the line
+    if marked(line.len()) {
+        marker.push('^');
+    }
+
+    let len = marker.trim_end().len();
+    marker.truncate(len);
+    marker
+}
+
+
+impl Default for Ui {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Ui {
 
     pub fn new() -> Ui {
         Ui {
-            mode: Mode::Command
+            mode: Mode::Command,
+            output: Output::new(),
+            color: false,
+            prompt: String::new(),
+            verbose: true,
+            pending: None
+        }
+    }
+
+    pub fn display<'a>(&mut self, model: DisplayModel<'a>) {
+
+        let highlighter = if self.color { model.highlighter } else { None };
+
+        for (n, section) in model.sections.iter().enumerate() {
+            if n > 0 {
+                self.output.push("--".to_string());
+            }
+
+            // the section may start inside of a multi-line construct
+            let mut state = match highlighter {
+                Some((highlighter, states)) => states.borrow_mut().state_at(highlighter, model.buffer, section.range.start),
+                None => highlight::State::Normal
+            };
+
+            let mut highlights = section.highlights.iter().peekable();
+
+            for (line_nr, line) in model.buffer.lines( &section.range ).enumerate() {
+                let index = line_nr + section.range.start;
+
+                let spans = if highlights.peek().is_some_and(|&&(i, _)| i == index) {
+                    &highlights.next().unwrap().1[..]
+                } else {
+                    &[]
+                };
+
+                let tokens = match highlighter {
+                    Some((highlighter, _)) => highlighter.highlight_line(line, &mut state),
+                    None => Vec::new()
+                };
+
+                let text = self.render(line, spans, &tokens, &model.option);
+                let output = match model.option {
+                    PrintOption::Numbered => format!("{}\t{}", self.paint(DIM, index + 1), text),
+                    _ => text
+                };
+
+                self.output.push(output);
+
+                // without colours, matches are marked on a line of their own
+                if !spans.is_empty() && !self.color {
+                    let prefix = if model.option == PrintOption::Numbered { "\t" } else { "" };
+                    self.output.push(marker_line(prefix, line, spans));
+                }
+            }
         }
+
     }
 
-    pub fn display<'a>(&self, model: DisplayModel<'a>) {
-        
-        for (line_nr, line) in model.buffer.get_lines( &model.range ).iter().enumerate() {
+    /// Renders `line` with `spans` highlighted and the syntax of `tokens`
+    /// coloured. With `l`, control characters and backslashes are escaped
+    /// and the end of the line is marked by `$`.
+    fn render(&self, line: &str, spans: &[ops::Range<usize>], tokens: &[Token], option: &PrintOption) -> String {
+        let escape = *option == PrintOption::LineEndings;
+        let mut output = String::with_capacity(line.len());
+        let mut current = None;
 
-            let output = match model.option {
-                PrintOption::Normal => format!("{}", line),
-                PrintOption::Numbered => format!("{}\t{}", line_nr + model.range.start + 1, line),
-                PrintOption::LineEndings => format!("{}$", line)
+        for (offset, c) in line.char_indices() {
+            let style = if self.color && spans.iter().any(|span| span.start <= offset && offset < span.end) {
+                Some(HIGHLIGHT)
+            } else {
+                tokens.iter()
+                    .find(|token| token.span.start <= offset && offset < token.span.end)
+                    .map(|token| syntax_color(token.style))
             };
 
-            println!("{}", output);
+            if style != current {
+                if current.is_some() {
+                    output.push_str(RESET);
+                }
+                if let Some(code) = style {
+                    output.push_str(code);
+                }
+                current = style;
+            }
+
+            match escape_char(c) {
+                Some(ref escaped) if escape && c.is_control() && current.is_none() => output.push_str(&self.paint(CONTROL, escaped)),
+                Some(ref escaped) if escape => output.push_str(escaped),
+                _ => output.push(c)
+            }
+        }
+
+        if current.is_some() {
+            output.push_str(RESET);
         }
 
+        if escape {
+            output.push('$');
+        }
+
+        output
+    }
+
+    /// Wraps `text` in the escape codes of `style` if colours are enabled.
+    fn paint<T: fmt::Display>(&self, style: &str, text: T) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn is_color(&self) -> bool {
+        self.color
+    }
+
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    /// The prompt shown before each command, empty for none.
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    pub fn set_prompt(&mut self, prompt: String) {
+        self.prompt = prompt;
+    }
+
+    /// Whether errors are reported in full rather than as a bare `?`.
+    pub fn is_verbose(&self) -> bool {
+        self.verbose
     }
 
-    pub fn get_input(&self, stdin: &io::Stdin) -> Result<Action> {
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    pub fn print<T: ToString>(&mut self, line: T) {
+        self.output.push(line.to_string());
+    }
+
+    /// Prints a line of a unified diff, colored by what it says.
+    pub fn print_diff(&mut self, line: String) {
+        let style = if line.starts_with("---") || line.starts_with("+++") {
+            DIFF_HEADER
+        } else if line.starts_with("@@") {
+            DIFF_HUNK
+        } else if line.starts_with('-') {
+            DIFF_DELETED
+        } else if line.starts_with('+') {
+            DIFF_INSERTED
+        } else {
+            return self.print(line);
+        };
+
+        let line = self.paint(style, line);
+        self.print(line);
+    }
+
+    pub fn take_output(&mut self) -> Output {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn get_input<R: BufRead>(&self, reader: &mut R) -> Result<Option<String>> {
 
         let mut input = String::new();
 
-        let len = try!(stdin.read_line(&mut input));
+        let len = reader.read_line(&mut input)?;
 
         if len == 0 {
-            return Ok(Action::Command(cmd::Cmd::Quit));
+            return Ok(None);
         }
 
-        let trim_input = input.trim_right();
+        Ok(Some(input))
+    }
+
+    pub fn write_output<W: Write>(&self, writer: &mut W, output: Output) -> Result<()> {
+        for line in output {
+            writeln!(writer, "{}", line)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_error<W: Write>(&self, writer: &mut W, error: &Error) -> Result<()> {
+        let message = match error.error {
+            ErrorType::Interrupted => "?".to_string(),
+            _ if self.verbose => format!("? {}", error),
+            _ => "?".to_string()
+        };
+        writeln!(writer, "{}", self.paint(ERROR, message))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Parses an input line according to the current mode, returning None
+    /// if the command continues on the next line.
+    ///
+    /// A command line ending in a backslash inside the replacement of `s` or
+    /// the command list of `g` and `v` is kept and joined with the next one.
+    ///
+    /// In insert mode the text is kept exactly as typed, only the newline is
+    /// removed. A line consisting of a single `.` ends the input; to insert
+    /// such a line, type `\.` instead. In general one backslash is removed
+    /// from a line made of backslashes followed by a `.`.
+    pub fn parse_input(&mut self, input: &str) -> Result<Option<Action>> {
 
         match self.mode {
-            Mode::Command => Ok(Action::Command(try!(trim_input.parse()))),
-            Mode::Insert if trim_input == "." => Ok(Action::InsertEnd), 
-            Mode::Insert => Ok(Action::Insert(trim_input.to_string()))
+            Mode::Command => {
+                let line = input.trim_end();
+                let text = match self.pending.take() {
+                    Some(text) => text + "\n" + line,
+                    None => line.to_string()
+                };
+
+                match text.parse() {
+                    Ok(command) => Ok(Some(Action::Command(command))),
+                    Err(ref e) if matches!(*e.error_type(), ErrorType::Unfinished) => {
+                        self.pending = Some(text);
+                        Ok(None)
+                    },
+                    Err(e) => Err(e)
+                }
+            },
+            Mode::Insert => {
+                let line = input.trim_end_matches('\n');
+
+                if line == "." {
+                    Ok(Some(Action::InsertEnd))
+                } else if is_escaped_dot(line) {
+                    Ok(Some(Action::Insert(line[1..].to_string())))
+                } else {
+                    Ok(Some(Action::Insert(line.to_string())))
+                }
+            }
         }
 
     }
 
+    /// Whether the last command line continues on the next one.
+    pub fn is_continued(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Drops the lines of an unfinished command.
+    pub fn cancel_continuation(&mut self) {
+        self.pending = None;
+    }
+
+    /// Switches modes the way handling `action` would, for parsing input
+    /// lines that are stored rather than executed.
+    pub fn follow(&mut self, action: &Action) {
+        match *action {
+            Action::Command(ref command) => if let cmd::Cmd::EnterInsertMode(_) = command.cmd {
+                self.mode = Mode::Insert;
+            },
+            Action::InsertEnd => self.mode = Mode::Command,
+            Action::Insert(_) => ()
+        }
+    }
+
+    pub fn is_insert_mode(&self) -> bool {
+        match self.mode {
+            Mode::Insert => true,
+            Mode::Command => false
+        }
+    }
+
     pub fn set_mode(&mut self, mode: Mode) {
         self.mode = mode;
     }
 
 }
+
+/// Whether `line` is one or more backslashes followed by a `.`, the escaped
+/// form of an inserted line that would otherwise end the input.
+fn is_escaped_dot(line: &str) -> bool {
+    line.len() > 1 && line.ends_with('.') && line[..line.len() - 1].chars().all(|c| c == '\\')
+}
@@ -0,0 +1,482 @@
+use std::cmp;
+use std::io;
+use std::io::Write;
+
+use document::Document;
+use pos;
+use pos::Converter;
+use term;
+use term::{
+    Key,
+    KeyInput,
+    RawMode,
+    TerminalInput
+};
+
+use Result;
+
+/// Columns between two tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// Terminal size assumed when it can't be determined.
+const DEFAULT_SIZE: (usize, usize) = (24, 80);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Mode {
+    Normal,
+    Insert
+}
+
+/// Number of screen columns taken by the first `count` characters of
+/// `line`.
+fn display_width(line: &str, count: usize) -> usize {
+    line.chars().take(count).fold(0, |width, c| match c {
+        '\t' => width + TAB_WIDTH - width % TAB_WIDTH,
+        _ => width + 1
+    })
+}
+
+/// The part of `line` shown from screen column `left` on, `width` columns
+/// wide, with tabs expanded and control characters replaced.
+fn visible(line: &str, left: usize, width: usize) -> String {
+    let mut expanded = String::new();
+    let mut column = 0;
+
+    for c in line.chars() {
+        match c {
+            '\t' => {
+                let next = column + TAB_WIDTH - column % TAB_WIDTH;
+                while column < next {
+                    expanded.push(' ');
+                    column += 1;
+                }
+            },
+            c if c.is_control() => {
+                expanded.push('?');
+                column += 1;
+            },
+            c => {
+                expanded.push(c);
+                column += 1;
+            }
+        }
+    }
+
+    expanded.chars().skip(left).take(width).collect()
+}
+
+/// Full-screen view of a document with a cursor, moved and edited with a
+/// few vi keys.
+///
+/// In normal mode: `h`, `j`, `k`, `l` and the cursor keys move, `0` and `$`
+/// go to the start and end of the line, `gg` and `G` to the first and last
+/// line, Ctrl-F and Ctrl-B page down and up. `x` deletes a character, `dd`
+/// a line. `i`, `a`, `I`, `A`, `o` and `O` start inserting. `m` and `'`
+/// set and jump to marks. `q` or `:` go back to the ed prompt.
+///
+/// In insert mode, typed text is inserted, Enter splits the line and
+/// Backspace at the start of a line joins it to the previous one. Escape
+/// goes back to normal mode.
+///
+/// All edits go through the document, so marks and the modified flag are
+/// shared with line mode. The undo history is not, `u` doesn't undo edits
+/// made here.
+struct Visual<'a> {
+    document: &'a mut Document,
+    row: usize,
+    col: usize,
+    top: usize,
+    left: usize,
+    rows: usize,
+    cols: usize,
+    mode: Mode,
+    pending: Option<char>,
+    message: String
+}
+
+/// Runs visual mode on `document` until the user leaves it, then makes the
+/// line the cursor was on the current line.
+pub fn run(document: &mut Document) -> Result<()> {
+    let (rows, cols) = term::size().unwrap_or(DEFAULT_SIZE);
+    let row = document.current_line().saturating_sub(1);
+
+    let mut visual = Visual {
+        document,
+        row,
+        col: 0,
+        top: 0,
+        left: 0,
+        rows: cmp::max(rows, 2),
+        cols,
+        mode: Mode::Normal,
+        pending: None,
+        message: String::new()
+    };
+
+    let raw = RawMode::enable()?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    // use the alternate screen, so the ed session is back afterwards
+    write!(out, "\x1b[?1049h")?;
+    let result = visual.main_loop(&mut TerminalInput, &mut out);
+    write!(out, "\x1b[?1049l")?;
+    out.flush()?;
+    drop(raw);
+
+    let line = if visual.document.is_empty() { 0 } else { visual.row + 1 };
+    visual.document.set_current_line(line);
+
+    result
+}
+
+impl <'a> Visual<'a> {
+
+    fn main_loop<R: KeyInput, W: Write>(&mut self, input: &mut R, out: &mut W) -> Result<()> {
+        loop {
+            self.clamp();
+            self.scroll();
+            self.draw(out)?;
+
+            let key = match term::read_key(input)? {
+                Some(key) => key,
+                None => return Ok(())
+            };
+
+            self.message.clear();
+
+            match self.mode {
+                Mode::Normal => if self.normal(key) {
+                    return Ok(());
+                },
+                Mode::Insert => self.insert(key)
+            }
+        }
+    }
+
+    fn line(&self, index: usize) -> &str {
+        self.document.buffer().get_line(index).unwrap_or("")
+    }
+
+    fn line_len(&self) -> usize {
+        self.line(self.row).chars().count()
+    }
+
+    fn last_row(&self) -> usize {
+        self.document.len().saturating_sub(1)
+    }
+
+    /// Keeps the cursor on the text, in normal mode on a character rather
+    /// than after the last one.
+    fn clamp(&mut self) {
+        self.row = cmp::min(self.row, self.last_row());
+
+        let len = self.line_len();
+        let max = if self.mode == Mode::Normal { len.saturating_sub(1) } else { len };
+        self.col = cmp::min(self.col, max);
+    }
+
+    /// Scrolls the view so that the cursor is visible.
+    fn scroll(&mut self) {
+        let text_rows = self.rows - 1;
+
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + text_rows {
+            self.top = self.row + 1 - text_rows;
+        }
+
+        let column = display_width(self.line(self.row), self.col);
+        if column < self.left {
+            self.left = column;
+        } else if column >= self.left + self.cols {
+            self.left = column + 1 - self.cols;
+        }
+    }
+
+    fn draw<W: Write>(&self, out: &mut W) -> Result<()> {
+        // hide the cursor while drawing
+        let mut screen = String::from("\x1b[?25l\x1b[H");
+
+        for index in self.top..self.top + self.rows - 1 {
+            match self.document.buffer().get_line(index) {
+                Some(line) => screen.push_str(&visible(line, self.left, self.cols)),
+                None => screen.push('~')
+            }
+            screen.push_str("\x1b[K\r\n");
+        }
+
+        let name = self.document.file_name().map_or("[no name]".to_string(), |name| name.to_string_lossy().into_owned());
+        let status = format!(" {}{} {} {},{} {}",
+                             name,
+                             if self.document.has_changes() { " [+]" } else { "" },
+                             if self.mode == Mode::Insert { "-- INSERT --" } else { "" },
+                             self.row + 1,
+                             self.col + 1,
+                             self.message);
+        let status: String = status.chars().take(self.cols).collect();
+        let padding = self.cols - status.chars().count();
+        screen.push_str(&format!("\x1b[7m{}{}\x1b[0m", status, " ".repeat(padding)));
+
+        let column = display_width(self.line(self.row), self.col) - self.left;
+        screen.push_str(&format!("\x1b[{};{}H\x1b[?25h", self.row - self.top + 1, column + 1));
+
+        out.write_all(screen.as_bytes())?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Handles a key in normal mode, returns whether to leave visual mode.
+    fn normal(&mut self, key: Key) -> bool {
+        if let Some(pending) = self.pending.take() {
+            match (pending, key) {
+                ('d', Key::Char('d')) => self.delete_line(),
+                ('g', Key::Char('g')) => self.row = 0,
+                ('m', Key::Char(c)) if c.is_ascii_lowercase() && !self.document.is_empty() => {
+                    self.document.set_mark(c, self.row + 1);
+                },
+                ('\'', Key::Char(c)) if c.is_ascii_lowercase() => {
+                    match self.document.convert(&pos::Pos::Mark(c)) {
+                        Ok(line) => {
+                            self.row = line.saturating_sub(1);
+                            self.col = 0;
+                        },
+                        Err(_) => self.message = format!("mark '{}' is not set", c)
+                    }
+                },
+                _ => ()
+            }
+            return false;
+        }
+
+        let page = self.rows - 1;
+
+        match key {
+            Key::Char('h') | Key::Left | Key::Backspace => self.col = self.col.saturating_sub(1),
+            Key::Char('l') | Key::Right | Key::Char(' ') => self.col += 1,
+            Key::Char('k') | Key::Up => self.row = self.row.saturating_sub(1),
+            Key::Char('j') | Key::Down | Key::Enter => self.row += 1,
+            Key::Char('0') | Key::Home => self.col = 0,
+            Key::Char('$') | Key::End => self.col = self.line_len(),
+            Key::Char('G') => self.row = self.last_row(),
+            Key::Ctrl('f') => self.row += page,
+            Key::Ctrl('b') => self.row = self.row.saturating_sub(page),
+            Key::Char(c @ 'd') | Key::Char(c @ 'g') | Key::Char(c @ 'm') | Key::Char(c @ '\'') => self.pending = Some(c),
+            Key::Char('x') | Key::Delete => self.delete_char(),
+            Key::Char('i') => self.mode = Mode::Insert,
+            Key::Char('a') => {
+                if self.line_len() > 0 {
+                    self.col += 1;
+                }
+                self.mode = Mode::Insert;
+            },
+            Key::Char('I') => {
+                self.col = 0;
+                self.mode = Mode::Insert;
+            },
+            Key::Char('A') => {
+                self.col = self.line_len();
+                self.mode = Mode::Insert;
+            },
+            Key::Char('o') => {
+                self.open_line(true);
+                self.mode = Mode::Insert;
+            },
+            Key::Char('O') => {
+                self.open_line(false);
+                self.mode = Mode::Insert;
+            },
+            Key::Char('q') | Key::Char(':') => return true,
+            _ => ()
+        }
+
+        false
+    }
+
+    fn insert(&mut self, key: Key) {
+        match key {
+            Key::Escape => {
+                self.mode = Mode::Normal;
+                self.col = self.col.saturating_sub(1);
+            },
+            Key::Enter => self.split_line(),
+            Key::Backspace if self.col > 0 => {
+                self.col -= 1;
+                self.delete_char();
+            },
+            Key::Backspace if self.row > 0 => self.join_line(),
+            Key::Delete => self.delete_char(),
+            Key::Left => self.col = self.col.saturating_sub(1),
+            Key::Right => self.col += 1,
+            Key::Up => self.row = self.row.saturating_sub(1),
+            Key::Down => self.row += 1,
+            Key::Home => self.col = 0,
+            Key::End => self.col = self.line_len(),
+            Key::Tab => self.insert_char('\t'),
+            Key::Char(c) => self.insert_char(c),
+            _ => ()
+        }
+    }
+
+    /// Makes sure there is a line to edit in an empty document.
+    fn ensure_line(&mut self) {
+        if self.document.is_empty() {
+            self.document.insert_lines(0, vec![String::new()]);
+        }
+    }
+
+    fn edit_line<F: FnOnce(&mut Vec<char>)>(&mut self, edit: F) {
+        self.ensure_line();
+
+        let mut chars: Vec<char> = self.line(self.row).chars().collect();
+        edit(&mut chars);
+        self.document.replace_line(self.row, chars.into_iter().collect());
+    }
+
+    fn insert_char(&mut self, c: char) {
+        let col = self.col;
+        self.edit_line(|chars| chars.insert(col, c));
+        self.col += 1;
+    }
+
+    fn delete_char(&mut self) {
+        let col = self.col;
+        if col < self.line_len() {
+            self.edit_line(|chars| {
+                chars.remove(col);
+            });
+        }
+    }
+
+    fn delete_line(&mut self) {
+        if !self.document.is_empty() {
+            self.document.delete_lines(&(self.row..self.row + 1));
+        }
+    }
+
+    /// Opens an empty line below or above the cursor and moves onto it.
+    fn open_line(&mut self, below: bool) {
+        if self.document.is_empty() {
+            self.ensure_line();
+        } else if below {
+            self.document.insert_lines(self.row + 1, vec![String::new()]);
+            self.row += 1;
+        } else {
+            self.document.insert_lines(self.row, vec![String::new()]);
+        }
+        self.col = 0;
+    }
+
+    fn split_line(&mut self) {
+        self.ensure_line();
+
+        let chars: Vec<char> = self.line(self.row).chars().collect();
+        let head = chars[..self.col].iter().cloned().collect();
+        let tail = chars[self.col..].iter().cloned().collect();
+
+        self.document.replace_line(self.row, head);
+        self.document.insert_lines(self.row + 1, vec![tail]);
+        self.row += 1;
+        self.col = 0;
+    }
+
+    fn join_line(&mut self) {
+        let previous = self.line(self.row - 1).to_string();
+        let joined = previous.clone() + self.line(self.row);
+
+        self.document.replace_line(self.row - 1, joined);
+        self.document.delete_lines(&(self.row..self.row + 1));
+        self.row -= 1;
+        self.col = previous.chars().count();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use buffer::Buffer;
+
+    /// Keys typed one at a time, so an escape is never followed by the
+    /// rest of a sequence.
+    struct Typed(io::Cursor<Vec<u8>>);
+
+    impl Read for Typed {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl KeyInput for Typed {
+        fn pending(&mut self, _timeout: i32) -> bool {
+            false
+        }
+    }
+
+    fn document(lines: &[&str]) -> Document {
+        let buffer: Buffer = lines.iter().map(|line| line.to_string()).collect();
+        Document::from_buffer(buffer, None)
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        document.lines(&(0..document.len())).collect()
+    }
+
+    /// Types `keys` into visual mode on `document`, returns the row the
+    /// cursor ends up on.
+    fn type_keys(document: &mut Document, keys: &str) -> usize {
+        let mut visual = Visual {
+            row: document.current_line().saturating_sub(1),
+            document,
+            col: 0,
+            top: 0,
+            left: 0,
+            rows: DEFAULT_SIZE.0,
+            cols: DEFAULT_SIZE.1,
+            mode: Mode::Normal,
+            pending: None,
+            message: String::new()
+        };
+
+        let mut input = Typed(io::Cursor::new(keys.as_bytes().to_vec()));
+        visual.main_loop(&mut input, &mut Vec::new()).unwrap();
+        visual.row
+    }
+
+    #[test]
+    fn inserts_into_an_empty_document() {
+        let mut document = document(&[]);
+        assert_eq!(type_keys(&mut document, "ihello\x1b"), 0);
+        assert_eq!(lines(&document), &["hello"]);
+        assert!(document.has_changes());
+    }
+
+    #[test]
+    fn deletes_and_opens_lines() {
+        let mut document = document(&["a", "b", "c"]);
+        assert_eq!(type_keys(&mut document, "jdd"), 1);
+        assert_eq!(lines(&document), &["a", "c"]);
+
+        assert_eq!(type_keys(&mut document, "onew\x1b"), 1);
+        assert_eq!(lines(&document), &["a", "new", "c"]);
+    }
+
+    #[test]
+    fn backspace_at_the_start_of_a_line_joins_it() {
+        let mut document = document(&["ab", "cd"]);
+        assert_eq!(type_keys(&mut document, "ji\x7fx\x1b"), 0);
+        assert_eq!(lines(&document), &["abxcd"]);
+    }
+
+    #[test]
+    fn marks_are_shared_with_line_mode() {
+        let mut document = document(&["a", "b", "c"]);
+        assert_eq!(type_keys(&mut document, "jjmagg'a"), 2);
+        assert_eq!(document.convert(&pos::Pos::Mark('a')).unwrap(), 3);
+
+        document.set_mark('b', 2);
+        assert_eq!(type_keys(&mut document, "'b"), 1);
+    }
+}